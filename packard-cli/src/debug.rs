@@ -33,24 +33,45 @@ impl DebugLogger {
 
     pub fn log_state(&self, runtime: &packard_core::Runtime) {
         self.log("=== STATE ===");
-        for (key, value) in &runtime.state().variables {
-            self.log(&format!("  {}: {:?}", key, value));
+        let mut variables: Vec<_> = runtime.state().variables.iter().collect();
+        variables.sort_by(|a, b| a.0.cmp(b.0));
+        for (key, value) in variables {
+            self.log(&format!("  {}: {}", key, packard_core::expr::format_value(value)));
         }
     }
 
+    pub fn log_seed(&self, seed: u64) {
+        self.log(&format!("RNG SEED {} (replay with --seed {})", seed, seed));
+    }
+
     pub fn log_scene(&self, scene_id: &str) {
         self.log(&format!("SCENE CHANGE -> {}", scene_id));
     }
 
-    pub fn log_choice(&self, choice_idx: usize, label: &str) {
-        self.log(&format!("CHOICE MADE -> {} ({})", choice_idx + 1, label));
+    pub fn log_rewind(&self, from: &str, to: &str, remaining: usize) {
+        self.log(&format!("REWIND {} -> {} ({} step(s) left in history)", from, to, remaining));
+    }
+
+    pub fn log_choice(&self, id: &str, label: &str) {
+        self.log(&format!("CHOICE MADE -> {} ({})", id, label));
     }
 
-    pub fn log_effects(&self, effects: &[packard_core::Effect]) {
-        if !effects.is_empty() {
-            self.log("EFFECTS APPLIED:");
-            for effect in effects {
-                self.log(&format!("  {} {} {}", effect.variable, effect.operation, effect.value));
+    /// Log where a choice led and only the variables it changed.
+    pub fn log_outcome(&self, outcome: &packard_core::ChoiceOutcome) {
+        self.log(&format!("SCENE CHANGE {} -> {}", outcome.from, outcome.to));
+        if !outcome.changes.is_empty() {
+            self.log("CHANGES:");
+            for change in &outcome.changes {
+                let before = match &change.before {
+                    Some(value) => packard_core::expr::format_value(value),
+                    None => "(unset)".to_string(),
+                };
+                self.log(&format!(
+                    "  {}: {} -> {}",
+                    change.variable,
+                    before,
+                    packard_core::expr::format_value(&change.after)
+                ));
             }
         }
     }
@@ -1,6 +1,7 @@
-use packard_core::{Vault, Runtime};
+use packard_core::{Manifest, Vault, Runtime, Severity, UndefinedPolicy};
 use std::io::{self, Write};
 use std::env;
+use std::path::{Path, PathBuf};
 
 mod debug;
 use debug::DebugLogger;
@@ -10,17 +11,94 @@ fn clear_screen() {
     io::stdout().flush().unwrap();
 }
 
-fn strip_wikilinks(content: &str) -> String {
-    // Remove wikilink syntax with optional effects: [[target|label]](effects)
-    let re = regex::Regex::new(r"\[\[([^\]|]+)\|([^\]]+)\]\](?:\([^)]*\))?").unwrap();
-    re.replace_all(content, "").to_string()
+/// In-game commands accepted at the choice prompt.
+enum Command {
+    Choose(usize),
+    Save(u32),
+    Load(u32),
+    Back,
+}
+
+fn parse_command(input: &str, choice_count: usize) -> Option<Command> {
+    let mut words = input.split_whitespace();
+    let command = words.next()?;
+    if command == "back" {
+        return Some(Command::Back);
+    }
+
+    let slot = match words.next() {
+        Some(s) => s.parse().ok()?,
+        None => 1,
+    };
+
+    match command {
+        "save" => Some(Command::Save(slot)),
+        "load" => Some(Command::Load(slot)),
+        _ => match command.parse::<usize>() {
+            Ok(n) if n > 0 && n <= choice_count => Some(Command::Choose(n - 1)),
+            _ => None,
+        },
+    }
+}
+
+/// Save slots live in `<vault>/.packard/saves/`, which Obsidian ignores.
+fn slot_path(vault_path: &str, slot: u32) -> PathBuf {
+    Path::new(vault_path)
+        .join(".packard")
+        .join("saves")
+        .join(format!("slot{}.yaml", slot))
+}
+
+/// Title, version and author from the manifest, if it names a title.
+fn story_banner(manifest: &Manifest) -> Option<String> {
+    let mut banner = manifest.title.clone()?;
+    if let Some(version) = &manifest.version {
+        banner.push_str(&format!(" (v{})", version));
+    }
+    if let Some(author) = &manifest.author {
+        banner.push_str(&format!("\nby {}", author));
+    }
+    Some(banner)
+}
+
+/// `packard check <vault>`: print every diagnostic and return the exit code.
+fn check(vault_path: &str) -> i32 {
+    let vault = match Vault::load(vault_path) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{}", e.render_from_disk());
+            return 1;
+        }
+    };
+
+    let diagnostics = vault.validate();
+    for diagnostic in &diagnostics {
+        eprintln!("{}\n", diagnostic.render_from_disk());
+    }
+
+    let errors = diagnostics.iter().filter(|d| d.severity == Severity::Error).count();
+    let warnings = diagnostics.len() - errors;
+    if diagnostics.is_empty() {
+        println!("{}: no problems found", vault_path);
+    } else {
+        println!("{}: {} error(s), {} warning(s)", vault_path, errors, warnings);
+    }
+
+    if errors > 0 { 1 } else { 0 }
 }
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    
+
+    if args.len() == 3 && args[1] == "check" {
+        std::process::exit(check(&args[2]));
+    }
+
     let mut vault_path = "";
     let mut debug_log = None;
+    let mut load_slot = None;
+    let mut strict = false;
+    let mut seed = None;
 
     let mut i = 1;
     while i < args.len() {
@@ -34,6 +112,34 @@ fn main() {
                     return;
                 }
             }
+            "--strict" => {
+                strict = true;
+                i += 1;
+            }
+            "--seed" => {
+                match args.get(i + 1).and_then(|s| s.parse::<u64>().ok()) {
+                    Some(n) => {
+                        seed = Some(n);
+                        i += 2;
+                    }
+                    None => {
+                        eprintln!("Error: --seed requires a number");
+                        return;
+                    }
+                }
+            }
+            "-l" | "--load" => {
+                match args.get(i + 1).and_then(|s| s.parse::<u32>().ok()) {
+                    Some(slot) => {
+                        load_slot = Some(slot);
+                        i += 2;
+                    }
+                    None => {
+                        eprintln!("Error: -l requires a slot number");
+                        return;
+                    }
+                }
+            }
             _ => {
                 vault_path = &args[i];
                 i += 1;
@@ -43,8 +149,16 @@ fn main() {
 
     if vault_path.is_empty() {
         println!("Usage: packard [OPTIONS] <vault_path>");
+        println!("       packard check <vault_path>");
         println!("Options:");
         println!("  -d, --debug <file>  Log debug information to file");
+        println!("  -l, --load <slot>   Resume a saved game");
+        println!("      --strict        Treat reading an unset variable as an error,");
+        println!("                      whatever packard.yaml says");
+        println!("      --seed <n>      Fix the random numbers to replay a playthrough");
+        println!();
+        println!("At the choice prompt, `back` undoes the last choice and");
+        println!("`save [slot]` / `load [slot]` manage saved games.");
         return;
     }
 
@@ -61,24 +175,48 @@ fn main() {
     let vault = match Vault::load(vault_path) {
         Ok(v) => v,
         Err(e) => {
-            eprintln!("Error loading vault: {}", e);
+            eprintln!("{}", e.render_from_disk());
             return;
         }
     };
-
     logger.log(&format!("Vault loaded. Scenes: {:?}", vault.list_scenes()));
+    let banner = story_banner(&vault.manifest);
 
-    // Create runtime starting from "start" scene
-    let mut runtime = match Runtime::new(vault, "start") {
+    // Create runtime starting from the manifest's start scene
+    let start = vault.start_scene().to_string();
+    let runtime = match seed {
+        Some(seed) => Runtime::with_seed(vault, &start, seed),
+        None => Runtime::new(vault, &start),
+    };
+    let mut runtime = match runtime {
         Ok(r) => r,
         Err(e) => {
-            eprintln!("Error initializing runtime: {}", e);
+            eprintln!("{}", e.render_from_disk());
             return;
         }
     };
+    if strict {
+        runtime.set_undefined_policy(UndefinedPolicy::Strict);
+    }
+
+    logger.log_seed(runtime.seed());
+    logger.log_scene(&start);
+
+    if let Some(slot) = load_slot {
+        if let Err(e) = runtime.restore(slot_path(vault_path, slot)) {
+            eprintln!("{}", e.render_from_disk());
+            return;
+        }
+        logger.log(&format!("LOADED slot {}", slot));
+        logger.log_seed(runtime.seed());
+        logger.log_scene(runtime.current_scene_id());
+    }
 
-    logger.log_scene("start");
     clear_screen();
+    if let Some(banner) = banner {
+        logger.log(&format!("Story: {}", banner.replace('\n', " ")));
+        println!("{}\n", banner);
+    }
 
     // Main loop
     loop {
@@ -95,21 +233,25 @@ fn main() {
         
         clear_screen();
         
-        // Show content (without wikilinks and dialogue)
-        let clean_content = strip_wikilinks(&scene.content);
-        let clean_content = packard_core::dialogue::strip_dialogue(&clean_content);
-        println!("{}", clean_content);
+        // Show prose rendered for the current state, without dialogue
+        println!("{}", runtime.render_narration());
 
         // Show dialogue separately
-        if !scene.dialogue.is_empty() {
+        let dialogue = runtime.render_dialogue();
+        if !dialogue.is_empty() {
             println!("\n{}", "-".repeat(40));
-            for line in &scene.dialogue {
+            for line in &dialogue {
                 println!("**{}**: \"{}\"", line.character, line.text);
             }
         }
         
         // Get available choices based on conditions
         let available_choices = runtime.available_choices();
+        let labels: Vec<String> = available_choices.iter().map(|(_, c)| runtime.choice_label(c)).collect();
+        for diagnostic in runtime.take_diagnostics() {
+            logger.log(&diagnostic.message);
+            eprintln!("{}\n", diagnostic.render_from_disk());
+        }
 
         if available_choices.is_empty() {
             println!("\n[End of narrative]");
@@ -118,43 +260,74 @@ fn main() {
 
         // Show choices
         println!();
-        for (display_idx, (_orig_idx, choice)) in available_choices.iter().enumerate() {
-            println!("{}. {}", display_idx + 1, choice.label);
+        for (display_idx, label) in labels.iter().enumerate() {
+            println!("{}. {}", display_idx + 1, label);
         }
 
         // Get user input
-        print!("\nSelect choice (1-{}): ", available_choices.len());
+        print!("\nSelect choice (1-{}), back, or save/load [slot]: ", available_choices.len());
         io::stdout().flush().unwrap();
 
         let mut input = String::new();
-        if io::stdin().read_line(&mut input).is_err() {
-            break;
+        match io::stdin().read_line(&mut input) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
         }
 
-        let choice_idx = match input.trim().parse::<usize>() {
-            Ok(n) if n > 0 && n <= available_choices.len() => n - 1,
-            _ => {
+        let choice_idx = match parse_command(&input, available_choices.len()) {
+            Some(Command::Choose(idx)) => idx,
+            Some(Command::Save(slot)) => {
+                match runtime.save(slot_path(vault_path, slot)) {
+                    Ok(()) => {
+                        logger.log(&format!("SAVED slot {}", slot));
+                        println!("Saved to slot {}.", slot);
+                    }
+                    Err(e) => eprintln!("{}", e.render_from_disk()),
+                }
+                continue;
+            }
+            Some(Command::Load(slot)) => {
+                match runtime.restore(slot_path(vault_path, slot)) {
+                    Ok(()) => {
+                        logger.log(&format!("LOADED slot {}", slot));
+                        logger.log_seed(runtime.seed());
+                        logger.log_scene(runtime.current_scene_id());
+                        clear_screen();
+                    }
+                    Err(e) => eprintln!("{}", e.render_from_disk()),
+                }
+                continue;
+            }
+            Some(Command::Back) => {
+                let from = runtime.current_scene_id().to_string();
+                match runtime.undo() {
+                    Ok(()) => {
+                        logger.log_rewind(&from, runtime.current_scene_id(), runtime.history().len());
+                        logger.log_state(&runtime);
+                        clear_screen();
+                    }
+                    Err(e) => println!("{}", e),
+                }
+                continue;
+            }
+            None => {
                 println!("Invalid choice. Try again.");
                 continue;
             }
         };
 
-        // Get the original index of the choice
-        let (orig_idx, _) = available_choices[choice_idx];
-        let choice = orig_idx;
-
-        let scene = runtime.current_scene();
-        let selected_choice = &scene.choices[choice];
-        logger.log_choice(choice, &selected_choice.label);
-        logger.log_effects(&selected_choice.effects);
+        let (_, selected) = available_choices[choice_idx];
+        let id = selected.id.clone();
+        logger.log_choice(&id, &labels[choice_idx]);
 
-        if let Err(e) = runtime.choose(choice) {
-            eprintln!("Error: {}", e);
-            break;
+        match runtime.choose_by_id(&id) {
+            Ok(outcome) => logger.log_outcome(&outcome),
+            Err(e) => {
+                eprintln!("{}", e.render_from_disk());
+                break;
+            }
         }
 
-        logger.log_scene(runtime.current_scene_id());
-        logger.log_state(&runtime);
         clear_screen();
     }
 }
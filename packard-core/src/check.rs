@@ -0,0 +1,365 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use crate::error::{render_snippet, PackardError};
+use crate::expr::Expr;
+use crate::parser::Span;
+use crate::vault::Vault;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in a vault. Errors break play; warnings are likely mistakes.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Short kebab-case identifier, e.g. "dangling-link"
+    pub code: &'static str,
+    pub message: String,
+    /// The file the problem is in, when it is not tied to a specific span.
+    pub file: Option<String>,
+    pub span: Option<Span>,
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code,
+            message: message.into(),
+            file: None,
+            span: None,
+        }
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(code, message)
+        }
+    }
+
+    pub fn with_span(mut self, span: Option<&Span>) -> Self {
+        if let Some(span) = span {
+            self.file = Some(span.file.clone());
+        }
+        self.span = span.cloned();
+        self
+    }
+
+    pub fn with_file(mut self, file: &str) -> Self {
+        self.file = Some(file.to_string());
+        self
+    }
+
+    /// Render the diagnostic rustc-style, e.g. `warning[dead-end]: ...`
+    pub fn render(&self, source: Option<&str>) -> String {
+        let level = match self.severity {
+            Severity::Error => format!("error[{}]", self.code),
+            Severity::Warning => format!("warning[{}]", self.code),
+        };
+        let mut out = render_snippet(&level, &self.message, self.span.as_ref(), source);
+        if let (None, Some(file)) = (&self.span, &self.file) {
+            out.push_str(&format!("\n --> {}", file));
+        }
+        out
+    }
+
+    /// Like [`Diagnostic::render`], reading the snippet from the file on disk.
+    pub fn render_from_disk(&self) -> String {
+        let source = self.span.as_ref().and_then(|s| std::fs::read_to_string(&s.file).ok());
+        self.render(source.as_deref())
+    }
+}
+
+impl From<PackardError> for Diagnostic {
+    fn from(error: PackardError) -> Self {
+        let code = match error {
+            PackardError::Syntax { .. } => "syntax",
+            PackardError::SceneNotFound { .. } => "dangling-link",
+            PackardError::Yaml { .. } => "yaml",
+            PackardError::UndefinedVariable { .. } => "undefined-variable",
+            PackardError::Runtime { .. } => "runtime",
+            _ => "error",
+        };
+        Diagnostic::error(code, error.message()).with_span(error.span())
+    }
+}
+
+/// Lint a loaded vault, reporting every problem found in one pass.
+pub fn check_vault(vault: &Vault) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut writes: Vec<(&str, Option<&Span>)> = vault.manifest.variables.keys().map(|v| (v.as_str(), None)).collect();
+    let mut reads: Vec<(&str, Option<&Span>)> = Vec::new();
+    let mut visits: Vec<(&str, Option<&Span>)> = Vec::new();
+    // Effects like `name = Bob`, which once stored the string "Bob"
+    let mut bare_words: Vec<(&str, Option<&Span>)> = Vec::new();
+    diagnostics.extend(vault.diagnostics.iter().cloned());
+
+    for id in vault.list_scenes() {
+        let scene = vault.get_scene(&id).unwrap();
+        diagnostics.extend(scene.diagnostics.iter().cloned());
+
+        for choice in &scene.choices {
+            let candidates = vault.link_candidates(&choice.target);
+            if candidates.is_empty() {
+                diagnostics.push(
+                    Diagnostic::error("dangling-link", format!("link target '{}' does not exist", choice.target))
+                        .with_span(Some(&choice.span)),
+                );
+            } else if candidates.len() > 1 {
+                diagnostics.push(
+                    Diagnostic::error(
+                        "ambiguous-link",
+                        format!("link target '{}' could be any of: {}", choice.target, candidates.join(", ")),
+                    )
+                    .with_span(Some(&choice.span)),
+                );
+            } else if let (Some(heading), Some(target)) = (&choice.heading, vault.get_scene(&choice.target)) {
+                if target.section(heading).is_none() {
+                    diagnostics.push(
+                        Diagnostic::warning(
+                            "unknown-heading",
+                            format!("scene '{}' has no heading '{}'", target.id, heading),
+                        )
+                        .with_span(Some(&choice.span)),
+                    );
+                }
+            }
+
+            if let Some(condition) = &choice.condition {
+                for variable in condition.variables() {
+                    reads.push((variable, choice.condition_span.as_ref()));
+                }
+                for name in condition.scene_names() {
+                    visits.push((name, choice.condition_span.as_ref()));
+                }
+            }
+            for effect in &choice.effects {
+                writes.push((effect.variable.as_str(), choice.effects_span.as_ref()));
+                if let Expr::Variable(word) = &effect.expr {
+                    bare_words.push((word, choice.effects_span.as_ref()));
+                }
+                for variable in effect.expr.variables() {
+                    reads.push((variable, choice.effects_span.as_ref()));
+                }
+                for name in effect.expr.scene_names() {
+                    visits.push((name, choice.effects_span.as_ref()));
+                }
+            }
+        }
+
+        for (condition, span) in scene.prose.conditions() {
+            for variable in condition.variables() {
+                reads.push((variable, Some(span)));
+            }
+            for name in condition.scene_names() {
+                visits.push((name, Some(span)));
+            }
+        }
+
+        let labels = scene.choices.iter().map(|c| &c.label_template);
+        for placeholder in std::iter::once(&scene.prose).chain(labels).flat_map(|t| t.placeholders()) {
+            if vault.is_property_path(&placeholder.path) {
+                if placeholder.default.is_none() && vault.property(&placeholder.path).is_none() {
+                    diagnostics.push(
+                        Diagnostic::warning("unknown-property", format!("no property '{}'", placeholder.path))
+                            .with_span(Some(&placeholder.span)),
+                    );
+                }
+            } else if placeholder.default.is_none() {
+                reads.push((placeholder.path.as_str(), Some(&placeholder.span)));
+            }
+        }
+
+        for (effects, span) in [(&scene.on_enter, &scene.on_enter_span), (&scene.on_exit, &scene.on_exit_span)] {
+            for effect in effects {
+                writes.push((effect.variable.as_str(), span.as_ref()));
+                if let Expr::Variable(word) = &effect.expr {
+                    bare_words.push((word, span.as_ref()));
+                }
+                for variable in effect.expr.variables() {
+                    reads.push((variable, span.as_ref()));
+                }
+                for name in effect.expr.scene_names() {
+                    visits.push((name, span.as_ref()));
+                }
+            }
+        }
+
+        if scene.choices.is_empty() && !scene.ending {
+            diagnostics.push(
+                Diagnostic::warning(
+                    "dead-end",
+                    format!("scene '{}' has no choices and is not marked `ending: true`", id),
+                )
+                .with_file(&scene.file),
+            );
+        }
+    }
+
+    // Placeholders read such a variable from the notes instead
+    let mut seen = HashSet::new();
+    for (variable, span) in writes.iter().chain(&reads) {
+        if vault.is_property_path(variable) && seen.insert(*variable) {
+            let kind = variable.split('.').next().unwrap_or(variable);
+            diagnostics.push(
+                Diagnostic::warning(
+                    "variable-shadowed",
+                    format!("variable '{}' starts with the note type '{}', so {{{}}} reads a note property instead", variable, kind, variable),
+                )
+                .with_span(*span),
+            );
+        }
+    }
+
+    let written: HashSet<&str> = writes.iter().map(|(variable, _)| *variable).collect();
+    for (variable, span) in reads {
+        if !written.contains(variable) {
+            let mut message = format!("variable '{}' is read but never written", variable);
+            if !variable.contains('.') && bare_words.contains(&(variable, span)) {
+                message.push_str(&format!("; did you mean the string \"{}\"?", variable));
+            }
+            diagnostics.push(Diagnostic::warning("unwritten-variable", message).with_span(span));
+        }
+    }
+
+    for (name, span) in visits {
+        if vault.resolve_visit(name).is_none() {
+            diagnostics.push(
+                Diagnostic::warning("unknown-scene", format!("no scene '{}' to count visits to", name)).with_span(span),
+            );
+        }
+    }
+
+    let start = vault.start_scene();
+    match vault.get_scene(start) {
+        Some(_) => {
+            let reachable = reachable_from(vault, start);
+            for id in vault.list_scenes() {
+                if !reachable.contains(id.as_str()) {
+                    diagnostics.push(
+                        Diagnostic::warning("unreachable", format!("scene '{}' cannot be reached from '{}'", id, start))
+                            .with_file(&vault.get_scene(&id).unwrap().file),
+                    );
+                }
+            }
+        }
+        None => diagnostics.push(Diagnostic::error(
+            "missing-start",
+            format!("start scene '{}' does not exist", start),
+        )),
+    }
+
+    diagnostics
+}
+
+/// Scene ids reachable by following choice links from `start`.
+fn reachable_from<'a>(vault: &'a Vault, start: &'a str) -> HashSet<&'a str> {
+    let links: HashMap<&str, Vec<&str>> = vault
+        .scenes
+        .values()
+        .map(|s| (s.id.as_str(), s.choices.iter().map(|c| c.target.as_str()).collect()))
+        .collect();
+
+    let mut seen = HashSet::from([start]);
+    let mut queue = VecDeque::from([start]);
+    while let Some(id) = queue.pop_front() {
+        for target in links.get(id).into_iter().flatten() {
+            if vault.get_scene(target).is_some() && seen.insert(*target) {
+                queue.push_back(target);
+            }
+        }
+    }
+    seen
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vault::tests::vault;
+
+    fn codes(diagnostics: &[Diagnostic]) -> Vec<&str> {
+        let mut codes: Vec<_> = diagnostics.iter().map(|d| d.code).collect();
+        codes.sort();
+        codes
+    }
+
+    #[test]
+    fn test_clean_vault() {
+        let vault = vault(&[
+            ("start", "[[end|Go]](seen = 1)\n{if: seen > 0}[[end|Again]]"),
+            ("end", "---\nending: true\n---\nDone."),
+        ]);
+        assert!(check_vault(&vault).is_empty());
+    }
+
+    #[test]
+    fn test_reports_every_problem() {
+        let vault = vault(&[
+            ("start", "[[missing|Go]]\n[[stuck|Stuck]](oops)\n{if: courage > 5}[[start|Loop]]"),
+            ("stuck", "Nothing here."),
+            ("island", "[[start|Back]]"),
+        ]);
+        let diagnostics = check_vault(&vault);
+        assert_eq!(
+            codes(&diagnostics),
+            vec!["dangling-link", "dead-end", "syntax", "unreachable", "unwritten-variable"]
+        );
+
+        let dangling = diagnostics.iter().find(|d| d.code == "dangling-link").unwrap();
+        assert_eq!(dangling.severity, Severity::Error);
+        assert_eq!(dangling.span.as_ref().unwrap().line, 1);
+
+        let syntax = diagnostics.iter().find(|d| d.code == "syntax").unwrap();
+        assert_eq!(syntax.span.as_ref().unwrap().column, 17);
+    }
+
+    #[test]
+    fn test_placeholders() {
+        let vault = vault(&[(
+            "start",
+            "---\nending: true\n---\n{player.name|\"you\"} meet {characters.ghost.name} with {score:03} gold.",
+        )]);
+        let diagnostics = check_vault(&vault);
+        assert_eq!(codes(&diagnostics), vec!["unknown-property", "unwritten-variable"]);
+        assert!(diagnostics.iter().any(|d| d.message.contains("'score'")));
+    }
+
+    #[test]
+    fn test_variables_named_like_note_types() {
+        let mut vault = vault(&[("start", "[[start|Take]](item.count += 1; items.count += 1)\n{if: relic.power > 0}[[start|Use]]")]);
+        let idol = crate::entity::Entity::from_markdown("idol".to_string(), "---\ntype: relic\n---\n").unwrap();
+        vault.entities.entry("relic".to_string()).or_default().insert("idol".to_string(), idol);
+
+        let diagnostics = check_vault(&vault);
+        assert_eq!(codes(&diagnostics), vec!["unwritten-variable", "variable-shadowed", "variable-shadowed"]);
+        let shadowed: Vec<_> = diagnostics.iter().filter(|d| d.code == "variable-shadowed").map(|d| d.message.as_str()).collect();
+        assert!(shadowed[0].contains("'item.count'") && shadowed[1].contains("'relic.power'"));
+    }
+
+    #[test]
+    fn test_bare_words_read_variables() {
+        let vault = vault(&[
+            ("start", "---\non_enter: \"mood = calm\"\n---\n[[end|Meet]](friend = Bob; rival = friend; ally = bob.name)"),
+            ("end", "---\nending: true\n---\n"),
+        ]);
+        let mut messages: Vec<_> = check_vault(&vault).into_iter().map(|d| (d.code, d.message)).collect();
+        messages.sort();
+        assert_eq!(
+            messages,
+            vec![
+                ("unwritten-variable", "variable 'Bob' is read but never written; did you mean the string \"Bob\"?".to_string()),
+                ("unwritten-variable", "variable 'bob.name' is read but never written".to_string()),
+                ("unwritten-variable", "variable 'calm' is read but never written; did you mean the string \"calm\"?".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_start_scene() {
+        let vault = vault(&[("intro", "---\nending: true\n---\n")]);
+        assert_eq!(codes(&check_vault(&vault)), vec!["missing-start"]);
+    }
+}
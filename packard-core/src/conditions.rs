@@ -1,155 +1,304 @@
-use regex::Regex;
+use std::collections::HashMap;
+use serde_yaml::Value;
 use crate::effects::State;
+use crate::error::{PackardError, Result};
+use crate::expr::{eval_pair, format_value, type_name, BinaryOp, Expr, Parser, Token};
+use crate::rng::Rng;
 
 #[derive(Debug, Clone)]
 pub struct SimpleCondition {
-    pub variable: String,
+    /// Left-hand side, usually a variable but any expression works,
+    /// e.g. `count(player.inventory)`.
+    pub left: Expr,
     pub operator: String, // ">", "<", ">=", "<=", "==", "!="
-    pub value: String,
+    /// Right-hand side: a literal, another variable or any expression,
+    /// e.g. `shop.price * 2`.
+    pub value: Expr,
 }
 
+/// A parsed condition. `AND`/`&&` binds tighter than `OR`/`||`; `NOT`/`!`
+/// binds tightest; parentheses group.
 #[derive(Debug, Clone)]
 pub enum Condition {
     Simple(SimpleCondition),
-    Compound(Vec<(Option<String>, SimpleCondition)>), // (operator, condition) where first operator is None
+    /// An expression that must be true or false on its own, e.g.
+    /// `has(player.inventory, "key")` or `"key" in player.inventory`.
+    Test(Expr),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+    Not(Box<Condition>),
 }
 
 impl SimpleCondition {
-    pub fn evaluate(&self, state: &State) -> Result<bool, String> {
-        let var_value = state
-            .get(&self.variable)
-            .ok_or(format!("Variable '{}' not found in state", self.variable))?;
-
-        // Get numeric value from state
-        let left = var_value
-            .as_i64()
-            .ok_or(format!("Variable '{}' is not a number", self.variable))?;
-
-        // Parse right side as number
-        let right: i64 = self.value.parse()
-            .map_err(|_| format!("Cannot parse '{}' as number", self.value))?;
-
-        let result = match self.operator.as_str() {
-            ">" => left > right,
-            "<" => left < right,
-            ">=" => left >= right,
-            "<=" => left <= right,
-            "==" => left == right,
-            "!=" => left != right,
-            _ => return Err(format!("Unknown operator: {}", self.operator)),
-        };
+    pub fn evaluate(&self, state: &State) -> Result<bool> {
+        self.evaluate_with(state, &mut state.rng.clone())
+    }
+
+    fn evaluate_with(&self, state: &State, rng: &mut Rng) -> Result<bool> {
+        let (left_value, right_value) = eval_pair(&self.left, &self.value, state, rng)?;
+
+        compare(&left_value, &self.operator, &right_value).map_err(|reason| {
+            PackardError::runtime(format!(
+                "Cannot compare {} ({}) with {} ({}) using '{}': {}",
+                self.left,
+                type_name(&left_value),
+                self.value,
+                type_name(&right_value),
+                self.operator,
+                reason
+            ))
+        })
+    }
+}
 
-        Ok(result)
+/// Compare two state values. Numbers, strings and booleans compare with
+/// values of their own type; anything can be tested for equality with null.
+fn compare(left: &Value, operator: &str, right: &Value) -> std::result::Result<bool, &'static str> {
+    let ordering = match (left, right) {
+        (Value::Number(l), Value::Number(r)) => match (l.as_i64(), r.as_i64()) {
+            (Some(l), Some(r)) => l.partial_cmp(&r),
+            _ => l.as_f64().partial_cmp(&r.as_f64()),
+        },
+        (Value::String(l), Value::String(r)) => l.partial_cmp(r),
+        (Value::Bool(l), Value::Bool(r)) if operator == "==" || operator == "!=" => l.partial_cmp(r),
+        (Value::Bool(_), Value::Bool(_)) => return Err("booleans can only be tested for equality"),
+        (Value::Sequence(_), Value::Sequence(_)) if operator == "==" || operator == "!=" => {
+            let equal = left == right;
+            return Ok(if operator == "==" { equal } else { !equal });
+        }
+        (Value::Null, _) | (_, Value::Null) if operator == "==" || operator == "!=" => {
+            let equal = left.is_null() && right.is_null();
+            return Ok(if operator == "==" { equal } else { !equal });
+        }
+        _ => return Err("type mismatch"),
+    };
+
+    let ordering = ordering.ok_or("values are not comparable")?;
+    match operator {
+        ">" => Ok(ordering.is_gt()),
+        "<" => Ok(ordering.is_lt()),
+        ">=" => Ok(ordering.is_ge()),
+        "<=" => Ok(ordering.is_le()),
+        "==" => Ok(ordering.is_eq()),
+        "!=" => Ok(ordering.is_ne()),
+        _ => Err("unknown operator"),
     }
 }
 
 impl Condition {
-    pub fn evaluate(&self, state: &State) -> Result<bool, String> {
-        match self {
-            Condition::Simple(cond) => cond.evaluate(state),
-            Condition::Compound(conditions) => {
-                if conditions.is_empty() {
-                    return Ok(true);
-                }
+    /// Evaluate the condition, short-circuiting `AND` and `OR` so the right
+    /// side is only evaluated (and can only fail) when it decides the result.
+    /// Random numbers come from a copy of the state's stream, which is left
+    /// where it was.
+    pub fn evaluate(&self, state: &State) -> Result<bool> {
+        self.evaluate_with(state, &mut state.rng.clone())
+    }
 
-                let mut result = conditions[0].1.evaluate(state)?;
+    /// Like [`Condition::evaluate`], drawing random numbers from the stream
+    /// forked for `site` (see [`Rng::fork`]), so conditions at different
+    /// sites roll independently.
+    pub fn evaluate_at(&self, state: &State, site: &str) -> Result<bool> {
+        self.evaluate_with(state, &mut state.rng.fork(site))
+    }
 
-                for (op, cond) in &conditions[1..] {
-                    let cond_result = cond.evaluate(state)?;
-                    match op.as_ref().map(|s| s.as_str()) {
-                        Some("AND") => result = result && cond_result,
-                        Some("OR") => result = result || cond_result,
-                        _ => return Err("Unknown logical operator".to_string()),
-                    }
+    fn evaluate_with(&self, state: &State, rng: &mut Rng) -> Result<bool> {
+        match self {
+            Condition::Simple(cond) => cond.evaluate_with(state, rng),
+            Condition::Test(expr) => {
+                match expr.eval_like(state, rng, Some(&Value::Bool(false)))? {
+                    Value::Bool(b) => Ok(b),
+                    other => Err(PackardError::runtime(format!(
+                        "Condition {} is {} ({}), not true or false",
+                        expr,
+                        format_value(&other),
+                        type_name(&other)
+                    ))),
                 }
+            }
+            Condition::And(left, right) => Ok(left.evaluate_with(state, rng)? && right.evaluate_with(state, rng)?),
+            Condition::Or(left, right) => Ok(left.evaluate_with(state, rng)? || right.evaluate_with(state, rng)?),
+            Condition::Not(inner) => Ok(!inner.evaluate_with(state, rng)?),
+        }
+    }
 
-                Ok(result)
+    /// Names of the state variables this condition reads.
+    pub fn variables(&self) -> Vec<&str> {
+        match self {
+            Condition::Simple(cond) => {
+                let mut variables = cond.left.variables();
+                variables.extend(cond.value.variables());
+                variables
+            }
+            Condition::Test(expr) => expr.variables(),
+            Condition::And(left, right) | Condition::Or(left, right) => {
+                let mut variables = left.variables();
+                variables.extend(right.variables());
+                variables
             }
+            Condition::Not(inner) => inner.variables(),
         }
     }
-}
 
-pub fn parse_condition(condition_str: &str) -> Result<Condition, String> {
-    let condition_str = condition_str.trim();
+    /// Scene names this condition passes to `visited` or `visits`.
+    pub fn scene_names(&self) -> Vec<&str> {
+        match self {
+            Condition::Simple(cond) => {
+                let mut names = cond.left.scene_names();
+                names.extend(cond.value.scene_names());
+                names
+            }
+            Condition::Test(expr) => expr.scene_names(),
+            Condition::And(left, right) | Condition::Or(left, right) => {
+                let mut names = left.scene_names();
+                names.extend(right.scene_names());
+                names
+            }
+            Condition::Not(inner) => inner.scene_names(),
+        }
+    }
 
-    // Check if it's a compound condition (contains AND or OR)
-    if condition_str.contains(" AND ") || condition_str.contains(" OR ") {
-        parse_compound_condition(condition_str)
-    } else {
-        parse_simple_condition(condition_str).map(Condition::Simple)
+    pub(crate) fn rename_scenes(&mut self, ids: &HashMap<String, String>) {
+        match self {
+            Condition::Simple(cond) => {
+                cond.left.rename_scenes(ids);
+                cond.value.rename_scenes(ids);
+            }
+            Condition::Test(expr) => expr.rename_scenes(ids),
+            Condition::And(left, right) | Condition::Or(left, right) => {
+                left.rename_scenes(ids);
+                right.rename_scenes(ids);
+            }
+            Condition::Not(inner) => inner.rename_scenes(ids),
+        }
     }
 }
 
-fn parse_simple_condition(condition_str: &str) -> Result<SimpleCondition, String> {
-    // Pattern: variable (op) value
-    let re = Regex::new(r"^([a-z_][a-z0-9_.]*)\s*(>=|<=|==|!=|>|<)\s*(.+)$")
-        .map_err(|e| format!("Regex error: {}", e))?;
-
-    if let Some(cap) = re.captures(condition_str.trim()) {
-        let variable = cap.get(1).unwrap().as_str().to_string();
-        let operator = cap.get(2).unwrap().as_str().to_string();
-        let value = cap.get(3).unwrap().as_str().trim().to_string();
+/// Boolean rules layered on the shared expression parser:
+///
+/// ```text
+/// or         := and (("OR" | "||") and)*
+/// and        := unary (("AND" | "&&") unary)*
+/// unary      := ("NOT" | "!") unary | "(" or ")" | comparison
+/// comparison := expr (operator expr)?
+/// ```
+///
+/// A comparison without an operator is a test that must itself be a boolean.
+impl Parser<'_> {
+    fn parse_or(&mut self) -> Result<Condition> {
+        let mut left = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            let right = self.parse_and()?;
+            left = Condition::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
 
-        Ok(SimpleCondition {
-            variable,
-            operator,
-            value,
-        })
-    } else {
-        Err(format!("Invalid condition syntax: {}", condition_str))
+    fn parse_and(&mut self) -> Result<Condition> {
+        let mut left = self.parse_unary_condition()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            let right = self.parse_unary_condition()?;
+            left = Condition::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
     }
-}
 
-fn parse_compound_condition(condition_str: &str) -> Result<Condition, String> {
-    let mut conditions = Vec::new();
-
-    // Split by AND and OR while preserving the operators
-    let parts: Vec<&str> = condition_str.split(" AND ").collect();
-    
-    if parts.len() > 1 {
-        // Contains AND operators
-        for (i, part) in parts.iter().enumerate() {
-            let simple = parse_simple_condition(part)?;
-            if i == 0 {
-                conditions.push((None, simple));
-            } else {
-                conditions.push((Some("AND".to_string()), simple));
+    fn parse_unary_condition(&mut self) -> Result<Condition> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.pos += 1;
+                Ok(Condition::Not(Box::new(self.parse_unary_condition()?)))
             }
-        }
-    } else {
-        // Try splitting by OR
-        let parts: Vec<&str> = condition_str.split(" OR ").collect();
-        if parts.len() > 1 {
-            for (i, part) in parts.iter().enumerate() {
-                let simple = parse_simple_condition(part)?;
-                if i == 0 {
-                    conditions.push((None, simple));
-                } else {
-                    conditions.push((Some("OR".to_string()), simple));
+            Some(Token::LParen) => {
+                // Either a grouped condition or an expression that starts
+                // with parentheses, e.g. `(a + b) > 2`.
+                let start = self.pos;
+                self.pos += 1;
+                let group = self.parse_or().and_then(|inner| {
+                    if self.peek() != Some(&Token::RParen) {
+                        return Err(self.error("')'"));
+                    }
+                    self.pos += 1;
+                    Ok(inner)
+                });
+                let continues = matches!(
+                    self.peek(),
+                    Some(Token::Compare(_) | Token::In | Token::Plus | Token::Minus | Token::Star | Token::Slash | Token::Percent)
+                );
+                if let (Ok(_), false) = (&group, continues) {
+                    return group;
+                }
+                self.pos = start;
+                match (self.parse_comparison(), group) {
+                    (Ok(comparison), _) => Ok(comparison),
+                    (Err(_), Err(group)) => Err(group),
+                    (Err(e), Ok(_)) => Err(e),
                 }
             }
-        } else {
-            // Single condition
-            return parse_simple_condition(condition_str).map(Condition::Simple);
+            _ => self.parse_comparison(),
         }
     }
 
-    Ok(Condition::Compound(conditions))
+    fn parse_comparison(&mut self) -> Result<Condition> {
+        let left = self.parse_expr()?;
+
+        let operator = match self.peek() {
+            Some(Token::Compare(op)) => op.to_string(),
+            _ if is_test(&left) => return Ok(Condition::Test(left)),
+            _ => return Err(self.error("a comparison operator")),
+        };
+        self.pos += 1;
+
+        let value = self.parse_expr()?;
+
+        Ok(Condition::Simple(SimpleCondition {
+            left,
+            operator,
+            value,
+        }))
+    }
+}
+
+/// Expressions that can stand alone as a condition: membership tests,
+/// `has(...)`, `visited(...)`, boolean literals and variables holding a flag.
+fn is_test(expr: &Expr) -> bool {
+    matches!(
+        expr,
+        Expr::Binary(BinaryOp::In, ..) | Expr::Variable(_) | Expr::Literal(Value::Bool(_))
+    ) || matches!(expr, Expr::Call(name, _) if name == "has" || name == "visited" || name == "chance")
+}
+
+pub fn parse_condition(condition_str: &str) -> Result<Condition> {
+    let mut parser = Parser::new(condition_str, "condition")?;
+    let condition = parser.parse_or()?;
+    parser.expect_end("'AND', 'OR' or end of condition")?;
+    Ok(condition)
+}
+
+#[cfg(test)]
+fn parse_simple_condition(condition_str: &str) -> Result<SimpleCondition> {
+    match parse_condition(condition_str)? {
+        Condition::Simple(simple) => Ok(simple),
+        _ => Err(PackardError::syntax(format!("Expected a single comparison: {}", condition_str))),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::effects::UndefinedPolicy;
 
     #[test]
     fn test_parse_simple_condition() {
         let cond = parse_simple_condition("player.health > 50").unwrap();
-        assert_eq!(cond.variable, "player.health");
+        assert_eq!(cond.left, Expr::Variable("player.health".to_string()));
         assert_eq!(cond.operator, ">");
-        assert_eq!(cond.value, "50");
+        assert_eq!(cond.value, Expr::Literal(Value::Number(50.into())));
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_evaluate_simple_condition_true() {
         let mut state = State::new();
         state.set("player.health", serde_yaml::Value::Number(100.into()));
@@ -159,6 +308,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_evaluate_simple_condition_false() {
         let mut state = State::new();
         state.set("player.health", serde_yaml::Value::Number(30.into()));
@@ -171,16 +321,16 @@ mod tests {
     fn test_parse_compound_condition_and() {
         let cond = parse_condition("player.health > 50 AND player.trust >= 30").unwrap();
         match cond {
-            Condition::Compound(conditions) => {
-                assert_eq!(conditions.len(), 2);
-                assert_eq!(conditions[0].0, None);
-                assert_eq!(conditions[1].0, Some("AND".to_string()));
+            Condition::And(left, right) => {
+                assert!(matches!(*left, Condition::Simple(ref c) if c.left == Expr::Variable("player.health".to_string())));
+                assert!(matches!(*right, Condition::Simple(ref c) if c.left == Expr::Variable("player.trust".to_string())));
             }
-            _ => panic!("Expected compound condition"),
+            _ => panic!("Expected AND condition"),
         }
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_evaluate_compound_condition_and_true() {
         let mut state = State::new();
         state.set("player.health", serde_yaml::Value::Number(100.into()));
@@ -191,6 +341,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_evaluate_compound_condition_and_false() {
         let mut state = State::new();
         state.set("player.health", serde_yaml::Value::Number(30.into()));
@@ -201,6 +352,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_evaluate_compound_condition_or_true() {
         let mut state = State::new();
         state.set("player.health", serde_yaml::Value::Number(30.into()));
@@ -211,6 +363,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_evaluate_compound_condition_or_false() {
         let mut state = State::new();
         state.set("player.health", serde_yaml::Value::Number(30.into()));
@@ -219,4 +372,146 @@ mod tests {
         let cond = parse_condition("player.health > 50 OR player.trust >= 30").unwrap();
         assert_eq!(cond.evaluate(&state).unwrap(), false);
     }
+
+    fn state(values: &[(&str, i64)]) -> State {
+        let mut state = State::new();
+        for (key, value) in values {
+            state.set(key, serde_yaml::Value::Number((*value).into()));
+        }
+        state
+    }
+
+    #[test]
+    fn test_and_binds_tighter_than_or() {
+        let cond = parse_condition("a > 1 AND b > 2 OR c > 3").unwrap();
+        assert!(matches!(cond, Condition::Or(..)));
+        assert!(cond.evaluate(&state(&[("a", 0), ("b", 0), ("c", 5)])).unwrap());
+        assert!(cond.evaluate(&state(&[("a", 5), ("b", 5), ("c", 0)])).unwrap());
+        assert!(!cond.evaluate(&state(&[("a", 5), ("b", 0), ("c", 0)])).unwrap());
+    }
+
+    #[test]
+    fn test_parentheses_and_not() {
+        let cond = parse_condition("a > 1 && !(b > 2 || c > 3)").unwrap();
+        assert!(cond.evaluate(&state(&[("a", 5), ("b", 0), ("c", 0)])).unwrap());
+        assert!(!cond.evaluate(&state(&[("a", 5), ("b", 0), ("c", 5)])).unwrap());
+
+        let cond = parse_condition("NOT (a > 1)").unwrap();
+        assert!(cond.evaluate(&state(&[("a", 0)])).unwrap());
+    }
+
+    #[test]
+    fn test_short_circuit_skips_missing_variables() {
+        let strict = |values: &[(&str, i64)]| State {
+            undefined: UndefinedPolicy::Strict,
+            ..state(values)
+        };
+
+        let cond = parse_condition("a > 1 OR missing > 2").unwrap();
+        assert!(cond.evaluate(&strict(&[("a", 5)])).unwrap());
+
+        let cond = parse_condition("a > 1 AND missing > 2").unwrap();
+        assert!(!cond.evaluate(&strict(&[("a", 0)])).unwrap());
+        assert!(cond.evaluate(&strict(&[("a", 5)])).is_err());
+    }
+
+    #[test]
+    fn test_invalid_conditions() {
+        for input in ["", "a >", "a > 1 AND", "(a > 1", "a > 1)", "a >> 2", "a 1"] {
+            assert!(parse_condition(input).is_err(), "{} should not parse", input);
+        }
+    }
+
+    #[test]
+    fn test_variables() {
+        let cond = parse_condition("a > 1 AND (b < 2 OR NOT c == 3)").unwrap();
+        assert_eq!(cond.variables(), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_string_and_bool_comparisons() {
+        let mut state = State::new();
+        state.set("player.inventory", Value::String("key".to_string()));
+        state.set("flag", Value::Bool(true));
+
+        assert!(parse_condition("player.inventory == \"key\"").unwrap().evaluate(&state).unwrap());
+        assert!(parse_condition("player.inventory != 'lamp'").unwrap().evaluate(&state).unwrap());
+        assert!(parse_condition("flag == true").unwrap().evaluate(&state).unwrap());
+        assert!(!parse_condition("flag != true").unwrap().evaluate(&state).unwrap());
+        assert!(parse_condition("flag != null").unwrap().evaluate(&state).unwrap());
+    }
+
+    #[test]
+    fn test_variable_to_variable_comparison() {
+        let state = state(&[("player.gold", 12), ("shop.price", 10)]);
+        let cond = parse_condition("player.gold >= shop.price").unwrap();
+        assert_eq!(cond.variables(), vec!["player.gold", "shop.price"]);
+        assert!(cond.evaluate(&state).unwrap());
+        assert!(!parse_condition("shop.price > player.gold").unwrap().evaluate(&state).unwrap());
+    }
+
+    #[test]
+    fn test_expression_operands() {
+        let state = state(&[("player.gold", 25), ("shop.price", 10)]);
+        let cond = parse_condition("player.gold >= shop.price * 2 + 5").unwrap();
+        assert_eq!(cond.variables(), vec!["player.gold", "shop.price"]);
+        assert!(cond.evaluate(&state).unwrap());
+        assert!(!parse_condition("player.gold > max(shop.price, 30)").unwrap().evaluate(&state).unwrap());
+        assert!(parse_condition("player.gold == -(-25)").unwrap().evaluate(&state).unwrap());
+    }
+
+    #[test]
+    fn test_list_conditions() {
+        let mut state = State::new();
+        state.set(
+            "player.inventory",
+            Value::Sequence(vec![Value::String("key".to_string()), Value::String("lamp".to_string())]),
+        );
+        state.set("flag", Value::Bool(true));
+
+        let holds = |input: &str| parse_condition(input).unwrap().evaluate(&state).unwrap();
+        assert!(holds("has(player.inventory, \"key\")"));
+        assert!(holds("\"lamp\" in player.inventory AND NOT \"rope\" in player.inventory"));
+        assert!(!holds("count(player.inventory) > 2"));
+        assert!(holds("(count(player.inventory) + 1) > 2"));
+        assert!(holds("flag && !missing"));
+        assert!(!holds("has(nothing, \"key\")"));
+
+        let cond = parse_condition("count(player.inventory) >= shop.minimum").unwrap();
+        assert_eq!(cond.variables(), vec!["player.inventory", "shop.minimum"]);
+        assert!(parse_condition("count(player.inventory)").is_err());
+        assert!(parse_condition("true AND 5 in 5").unwrap().evaluate(&state).is_err());
+    }
+
+    #[test]
+    fn test_type_mismatch_errors() {
+        let mut state = state(&[("player.gold", 12)]);
+        state.set("flag", Value::Bool(true));
+
+        let err = parse_condition("player.gold == \"ten\"").unwrap().evaluate(&state).unwrap_err();
+        assert!(err.to_string().contains("player.gold (number)"), "{}", err);
+        assert!(err.to_string().contains("\"ten\" (string)"), "{}", err);
+        assert!(parse_condition("flag > false").unwrap().evaluate(&state).is_err());
+    }
+
+    #[test]
+    fn test_undefined_variables_default_to_matching_type() {
+        let state = State::new();
+        assert!(!parse_condition("player.curiosity > 20").unwrap().evaluate(&state).unwrap());
+        assert!(parse_condition("player.curiosity == 0").unwrap().evaluate(&state).unwrap());
+        assert!(parse_condition("flag == false").unwrap().evaluate(&state).unwrap());
+        assert!(parse_condition("name == \"\"").unwrap().evaluate(&state).unwrap());
+        assert!(parse_condition("a == b").unwrap().evaluate(&state).unwrap());
+    }
+
+    #[test]
+    fn test_undefined_variables_strict() {
+        let mut state = State::new();
+        state.undefined = UndefinedPolicy::Strict;
+
+        match parse_condition("player.curiosity > 20").unwrap().evaluate(&state) {
+            Err(PackardError::UndefinedVariable { name, .. }) => assert_eq!(name, "player.curiosity"),
+            other => panic!("Expected UndefinedVariable, got {:?}", other),
+        }
+    }
 }
@@ -1,25 +1,72 @@
 use std::collections::HashMap;
+use std::sync::OnceLock;
 use regex::Regex;
+use crate::error::{PackardError, Result};
+use crate::expr::{binary, empty_list, eval_pair, BinaryOp, Expr, Parser};
+use crate::rng::Rng;
 
 #[derive(Debug, Clone)]
 pub struct Effect {
     pub variable: String,
     pub operation: String, // "=", "+=", "-=", etc.
+    /// The right-hand side as written.
     pub value: String,
+    pub expr: Expr,
+}
+
+/// A variable changed by a batch of effects.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub variable: String,
+    /// `None` if the variable had never been set
+    pub before: Option<serde_yaml::Value>,
+    pub after: serde_yaml::Value,
+}
+
+/// How reads of a variable that has never been set are treated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UndefinedPolicy {
+    /// Missing variables read as 0, false or "" to match what they are compared with.
+    #[default]
+    Default,
+    /// Reading a missing variable is an error.
+    Strict,
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct State {
     pub variables: HashMap<String, serde_yaml::Value>,
+    pub undefined: UndefinedPolicy,
+    /// Source for `random()`; part of the state so undo rolls it back too.
+    pub rng: Rng,
+    /// Times each scene was entered and each choice taken, the latter keyed
+    /// Obsidian block-link style as `scene#^choice-id`.
+    pub visits: HashMap<String, u32>,
+    /// Number of choices taken so far.
+    pub turns: u32,
 }
 
 impl State {
     pub fn new() -> Self {
         State {
             variables: HashMap::new(),
+            undefined: UndefinedPolicy::Default,
+            rng: Rng::default(),
+            visits: HashMap::new(),
+            turns: 0,
         }
     }
 
+    /// How many times a scene (or `scene#^choice-id`) has been visited.
+    pub fn visits(&self, id: &str) -> u32 {
+        self.visits.get(id).copied().unwrap_or(0)
+    }
+
+    pub fn record_visit(&mut self, id: &str) {
+        *self.visits.entry(id.to_string()).or_insert(0) += 1;
+    }
+
     pub fn set(&mut self, key: &str, value: serde_yaml::Value) {
         self.variables.insert(key.to_string(), value);
     }
@@ -28,75 +75,160 @@ impl State {
         self.variables.get(key)
     }
 
-    pub fn apply_effects(&mut self, effects: &[Effect]) -> Result<(), String> {
+    /// Read a variable, applying the undefined-variable policy if it is missing.
+    /// Under the default policy `like` picks the stand-in: "" for strings,
+    /// false for booleans, an empty list for lists and 0 otherwise.
+    pub fn read(&self, key: &str, like: Option<&serde_yaml::Value>) -> Result<serde_yaml::Value> {
+        if let Some(value) = self.get(key) {
+            return Ok(value.clone());
+        }
+
+        match self.undefined {
+            UndefinedPolicy::Strict => Err(PackardError::UndefinedVariable {
+                name: key.to_string(),
+                span: None,
+            }),
+            UndefinedPolicy::Default => Ok(match like {
+                Some(serde_yaml::Value::String(_)) => serde_yaml::Value::String(String::new()),
+                Some(serde_yaml::Value::Bool(_)) => serde_yaml::Value::Bool(false),
+                Some(serde_yaml::Value::Sequence(_)) => empty_list(),
+                _ => serde_yaml::Value::Number(0.into()),
+            }),
+        }
+    }
+
+    /// Apply a batch of effects all-or-nothing: if any effect fails the state
+    /// is left untouched. Returns the variables whose values changed, in the
+    /// order they were first written.
+    pub fn apply_effects(&mut self, effects: &[Effect]) -> Result<Vec<Change>> {
+        let mut rng = self.rng.clone();
+        let changes = self.apply_with(effects, &mut rng)?;
+        self.rng = rng;
+        Ok(changes)
+    }
+
+    /// Like [`State::apply_effects`], drawing random numbers from the stream
+    /// forked for `site` (see [`Rng::fork`]) and leaving the state's own
+    /// stream where it was.
+    pub fn apply_effects_at(&mut self, effects: &[Effect], site: &str) -> Result<Vec<Change>> {
+        let mut rng = self.rng.fork(site);
+        self.apply_with(effects, &mut rng)
+    }
+
+    fn apply_with(&mut self, effects: &[Effect], rng: &mut Rng) -> Result<Vec<Change>> {
+        let mut next = self.clone();
         for effect in effects {
-            self.apply_effect(effect)?;
+            next.apply_effect(effect, rng)?;
         }
-        Ok(())
+
+        let changes = next.changes_since(self, effects);
+        *self = next;
+        Ok(changes)
     }
 
-    fn apply_effect(&mut self, effect: &Effect) -> Result<(), String> {
-        match effect.operation.as_str() {
-            "=" => {
-                // Parse value as number or string
-                if let Ok(num) = effect.value.parse::<i64>() {
-                    self.set(&effect.variable, serde_yaml::Value::Number(num.into()));
-                } else if effect.value == "true" {
-                    self.set(&effect.variable, serde_yaml::Value::Bool(true));
-                } else if effect.value == "false" {
-                    self.set(&effect.variable, serde_yaml::Value::Bool(false));
-                } else {
-                    self.set(&effect.variable, serde_yaml::Value::String(effect.value.clone()));
-                }
-            }
-            "+=" => {
-                let current = self.get(&effect.variable)
-                    .and_then(|v| v.as_i64())
-                    .unwrap_or(0);
-                let delta: i64 = effect.value.parse()
-                    .map_err(|_| format!("Invalid number for +=: {}", effect.value))?;
-                self.set(&effect.variable, serde_yaml::Value::Number((current + delta).into()));
+    /// The variables written by `effects` whose values now differ from
+    /// `earlier`, in the order they were first written.
+    pub fn changes_since<'a>(&self, earlier: &State, effects: impl IntoIterator<Item = &'a Effect>) -> Vec<Change> {
+        let mut changes: Vec<Change> = Vec::new();
+        let mut seen = std::collections::HashSet::new();
+        for effect in effects {
+            let variable = effect.variable.as_str();
+            if !seen.insert(variable) {
+                continue;
             }
-            "-=" => {
-                let current = self.get(&effect.variable)
-                    .and_then(|v| v.as_i64())
-                    .unwrap_or(0);
-                let delta: i64 = effect.value.parse()
-                    .map_err(|_| format!("Invalid number for -=: {}", effect.value))?;
-                self.set(&effect.variable, serde_yaml::Value::Number((current - delta).into()));
+            let before = earlier.get(variable).cloned();
+            let after = self.get(variable).cloned().unwrap_or(serde_yaml::Value::Null);
+            if before.as_ref() != Some(&after) {
+                changes.push(Change { variable: variable.to_string(), before, after });
             }
-            _ => return Err(format!("Unknown operation: {}", effect.operation)),
         }
+        changes
+    }
+
+    fn apply_effect(&mut self, effect: &Effect, rng: &mut Rng) -> Result<()> {
+        let value = match effect.operation.as_str() {
+            "=" => effect.expr.eval(self, rng)?,
+            "+=" | "-=" => {
+                let op = if effect.operation == "+=" { BinaryOp::Add } else { BinaryOp::Sub };
+                let (current, delta) = if self.get(&effect.variable).is_some() {
+                    let current = Expr::Variable(effect.variable.clone());
+                    eval_pair(&current, &effect.expr, self, rng)?
+                } else {
+                    // Adding or removing a string or list on a variable that
+                    // was never written starts a list, so `inventory += "key"`
+                    // just works
+                    let delta = effect.expr.eval(self, rng)?;
+                    let like = if delta.is_string() || delta.is_sequence() { empty_list() } else { delta.clone() };
+                    (self.read(&effect.variable, Some(&like))?, delta)
+                };
+                binary(op, &current, &delta)?
+            }
+            _ => return Err(PackardError::runtime(format!("Unknown operation: {}", effect.operation))),
+        };
+        self.set(&effect.variable, value);
         Ok(())
     }
 }
 
-pub fn parse_effects(effects_str: &str) -> Result<Vec<Effect>, String> {
+/// Split on `;` outside of quoted strings.
+fn split_effects(effects_str: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in effects_str.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, ';') => {
+                parts.push(&effects_str[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&effects_str[start..]);
+    parts
+}
+
+/// `variable (op) value`, compiled on first use.
+fn effect_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| Regex::new(r"^([a-z_][a-z0-9_.]*)\s*(\+=|-=|=)\s*(.+)$").unwrap())
+}
+
+pub fn parse_effects(effects_str: &str) -> Result<Vec<Effect>> {
     let mut effects = Vec::new();
     
     // Split by semicolon for multiple effects
-    for effect_expr in effects_str.split(';') {
+    for effect_expr in split_effects(effects_str) {
         let effect_expr = effect_expr.trim();
         if effect_expr.is_empty() {
             continue;
         }
 
         // Match pattern: variable (op) value
-        let re = Regex::new(r"^([a-z_][a-z0-9_.]*)\s*(\+=|-=|=)\s*(.+)$")
-            .map_err(|e| format!("Regex error: {}", e))?;
-
-        if let Some(cap) = re.captures(effect_expr) {
+        if let Some(cap) = effect_pattern().captures(effect_expr) {
             let variable = cap.get(1).unwrap().as_str().to_string();
+            if variable == "turns" {
+                return Err(PackardError::syntax(format!(
+                    "Invalid effect syntax: 'turns' is counted automatically and cannot be assigned: {}",
+                    effect_expr
+                )));
+            }
             let operation = cap.get(2).unwrap().as_str().to_string();
             let value = cap.get(3).unwrap().as_str().trim().to_string();
+            let mut parser = Parser::new(&value, "effect")?;
+            let expr = parser.parse_expr()?;
+            parser.expect_end("an operator or end of effect")?;
 
             effects.push(Effect {
                 variable,
                 operation,
                 value,
+                expr,
             });
         } else {
-            return Err(format!("Invalid effect syntax: {}", effect_expr));
+            return Err(PackardError::syntax(format!("Invalid effect syntax: {}", effect_expr)));
         }
     }
 
@@ -137,6 +269,16 @@ mod tests {
         assert_eq!(state.get("player.kindness").unwrap().as_i64(), Some(10));
     }
 
+    #[test]
+    fn test_apply_effects_quoted_string() {
+        let mut state = State::new();
+        let effects = parse_effects("player.inventory = \"key\"; player.title = 'Sir'").unwrap();
+        state.apply_effects(&effects).unwrap();
+
+        assert_eq!(state.get("player.inventory").unwrap().as_str(), Some("key"));
+        assert_eq!(state.get("player.title").unwrap().as_str(), Some("Sir"));
+    }
+
     #[test]
     fn test_apply_effects_increment() {
         let mut state = State::new();
@@ -147,4 +289,127 @@ mod tests {
         
         assert_eq!(state.get("counter").unwrap().as_i64(), Some(8));
     }
+
+    #[test]
+    fn test_increment_undefined_variable() {
+        let effects = parse_effects("counter += 3").unwrap();
+
+        let mut state = State::new();
+        state.apply_effects(&effects).unwrap();
+        assert_eq!(state.get("counter").unwrap().as_i64(), Some(3));
+
+        let mut state = State::new();
+        state.undefined = UndefinedPolicy::Strict;
+        assert!(matches!(
+            state.apply_effects(&effects),
+            Err(PackardError::UndefinedVariable { .. })
+        ));
+    }
+
+    #[test]
+    fn test_apply_expressions() {
+        let mut state = State::new();
+        state.set("player.gold", serde_yaml::Value::Number(50.into()));
+        state.set("item.price", serde_yaml::Value::Number(12.into()));
+        state.set("player.hp", serde_yaml::Value::Number(98.into()));
+
+        let effects = parse_effects(
+            "player.gold = player.gold - item.price * 2; player.hp = clamp(player.hp + 5, 0, 100); player.name = \"Ada\" + \"; the Brave\"",
+        )
+        .unwrap();
+        assert_eq!(effects.len(), 3);
+        state.apply_effects(&effects).unwrap();
+
+        assert_eq!(state.get("player.gold").unwrap().as_i64(), Some(26));
+        assert_eq!(state.get("player.hp").unwrap().as_i64(), Some(100));
+        assert_eq!(state.get("player.name").unwrap().as_str(), Some("Ada; the Brave"));
+    }
+
+    #[test]
+    fn test_random_is_reproducible() {
+        let effects = parse_effects("roll = random(1, 6); roll += random(1, 6)").unwrap();
+        let roll = |seed| {
+            let mut state = State::new();
+            state.rng = Rng::new(seed);
+            state.apply_effects(&effects).unwrap();
+            state.get("roll").unwrap().as_i64().unwrap()
+        };
+        assert_eq!(roll(42), roll(42));
+        assert!((2..=12).contains(&roll(42)));
+    }
+
+    #[test]
+    fn test_invalid_effects() {
+        for input in ["gold = ", "gold = 1 +", "turns += 1", "gold = hello world", "gold = nope(1)", "gold"] {
+            assert!(parse_effects(input).is_err(), "{} should not parse", input);
+        }
+
+        let mut state = State::new();
+        let effects = parse_effects("gold = 10 / (gold - gold)").unwrap();
+        assert!(state.apply_effects(&effects).is_err());
+    }
+
+    #[test]
+    fn test_adding_to_unset_variable() {
+        let mut state = State::new();
+        state.apply_effects(&parse_effects("player.inventory += \"key\"; player.inventory += \"lamp\"; gone -= \"key\"").unwrap()).unwrap();
+        let items: Vec<_> = state.get("player.inventory").unwrap().as_sequence().unwrap().iter().map(|v| v.as_str().unwrap()).collect();
+        assert_eq!(items, vec!["key", "lamp"]);
+        assert_eq!(state.get("gone").unwrap().as_sequence().map(|l| l.len()), Some(0));
+        let has_key = crate::conditions::parse_condition("has(player.inventory, \"key\")").unwrap();
+        assert!(has_key.evaluate(&state).unwrap());
+
+        let mut state = State::new();
+        state.undefined = UndefinedPolicy::Strict;
+        let err = state.apply_effects(&parse_effects("bag += \"key\"").unwrap()).unwrap_err();
+        assert!(matches!(err, PackardError::UndefinedVariable { .. }));
+    }
+
+    #[test]
+    fn test_list_effects() {
+        let mut state = State::new();
+        let effects = parse_effects("inv += \"key\"; inv += \"lamp\"; inv -= \"key\"; inv += [\"rope\"]").unwrap();
+        state.apply_effects(&effects).unwrap();
+
+        let items: Vec<_> = state.get("inv").unwrap().as_sequence().unwrap().iter().map(|v| v.as_str().unwrap()).collect();
+        assert_eq!(items, vec!["lamp", "rope"]);
+
+        let mut state = State::new();
+        state.set("title", serde_yaml::Value::String("Sir".to_string()));
+        state.apply_effects(&parse_effects("title += \" Ada\"; bag = []").unwrap()).unwrap();
+        assert_eq!(state.get("title").unwrap().as_str(), Some("Sir Ada"));
+        assert_eq!(state.get("bag").unwrap().as_sequence().map(|l| l.len()), Some(0));
+    }
+
+    #[test]
+    fn test_failed_batch_leaves_state_untouched() {
+        let mut state = State::new();
+        state.set("player.boldness", serde_yaml::Value::Number(5.into()));
+        state.set("flag", serde_yaml::Value::Bool(true));
+
+        let effects = parse_effects("player.inventory += \"key\"; player.boldness += flag").unwrap();
+        assert!(state.apply_effects(&effects).is_err());
+        assert!(state.get("player.inventory").is_none());
+        assert_eq!(state.get("player.boldness").unwrap().as_i64(), Some(5));
+    }
+
+    #[test]
+    fn test_apply_effects_reports_changes() {
+        let mut state = State::new();
+        state.set("gold", serde_yaml::Value::Number(20.into()));
+        state.set("hp", serde_yaml::Value::Number(10.into()));
+
+        let changes = state.apply_effects(&parse_effects("gold -= 5; seen = true; hp = 10; gold -= 5").unwrap()).unwrap();
+        assert_eq!(
+            changes,
+            vec![
+                Change {
+                    variable: "gold".to_string(),
+                    before: Some(serde_yaml::Value::Number(20.into())),
+                    after: serde_yaml::Value::Number(10.into()),
+                },
+                Change { variable: "seen".to_string(), before: None, after: serde_yaml::Value::Bool(true) },
+            ]
+        );
+    }
 }
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+use crate::error::Result;
+use crate::parser;
+
+/// A note that is not a scene: a character, an item, a location or any
+/// custom `type`. Its frontmatter `name` and other properties are kept as
+/// written, and its body is the description.
+#[derive(Debug, Clone)]
+pub struct Entity {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub properties: HashMap<String, serde_yaml::Value>,
+}
+
+impl Entity {
+    pub fn from_markdown(id: String, content: &str) -> Result<Self> {
+        let file = format!("{}.md", id);
+        Self::from_source(id, content, &file)
+    }
+
+    /// Parse a note, reporting frontmatter errors against `file`.
+    pub fn from_source(id: String, content: &str, file: &str) -> Result<Self> {
+        // Split frontmatter from content
+        let (frontmatter, body, _) = parser::split_frontmatter(content);
+
+        // Parse YAML frontmatter
+        let mut name = id.clone();
+        let mut properties = HashMap::new();
+
+        if let Some(frontmatter) = frontmatter {
+            let data = parser::parse_frontmatter(&parser::Source::new(file, content), frontmatter)?;
+
+            // Extract name
+            if let Some(name_val) = data.get("name") {
+                if let Some(name_str) = name_val.as_str() {
+                    name = name_str.to_string();
+                }
+            }
+
+            // Store all properties for later access
+            if let Some(obj) = data.as_mapping() {
+                for (key, val) in obj {
+                    if let Some(key_str) = key.as_str() {
+                        if key_str != "name" {
+                            properties.insert(key_str.to_string(), val.clone());
+                        }
+                    }
+                }
+            }
+        }
+
+        let description = body.trim().to_string();
+
+        Ok(Entity {
+            id,
+            name,
+            description,
+            properties,
+        })
+    }
+
+    pub fn get_property(&self, key: &str) -> Option<&serde_yaml::Value> {
+        self.properties.get(key)
+    }
+}
@@ -0,0 +1,198 @@
+use std::fmt;
+use std::path::PathBuf;
+use crate::parser::Span;
+
+pub type Result<T> = std::result::Result<T, PackardError>;
+
+/// Every error packard-core can produce. Variants that point into a note carry
+/// the span of the offending markup so front-ends can show where it went wrong.
+#[derive(Debug)]
+pub enum PackardError {
+    /// A file or directory could not be read.
+    Io { path: PathBuf, source: std::io::Error },
+    /// The vault folder exists but cannot be played (e.g. it has no scenes).
+    InvalidVault { path: PathBuf, message: String },
+    /// A note's frontmatter is not valid YAML.
+    Yaml { span: Option<Span>, source: serde_yaml::Error },
+    /// Effect or condition markup could not be parsed.
+    Syntax { message: String, span: Option<Span> },
+    /// A scene id (from a link or the start scene) does not exist.
+    SceneNotFound { id: String, span: Option<Span> },
+    /// A choice index outside the current scene's choices.
+    InvalidChoice { index: usize },
+    /// No choice in the current scene has this id.
+    ChoiceNotFound { id: String },
+    /// The choice exists but its condition does not currently hold.
+    ChoiceUnavailable { id: String, span: Option<Span> },
+    /// A variable was read before being set while the vault is in strict mode.
+    UndefinedVariable { name: String, span: Option<Span> },
+    /// Evaluating a condition or applying an effect failed at play time.
+    Runtime { message: String, span: Option<Span> },
+    /// A save file is unreadable or does not match the loaded vault.
+    InvalidSave { path: Option<PathBuf>, message: String },
+}
+
+impl PackardError {
+    pub fn syntax(message: impl Into<String>) -> Self {
+        PackardError::Syntax { message: message.into(), span: None }
+    }
+
+    pub fn runtime(message: impl Into<String>) -> Self {
+        PackardError::Runtime { message: message.into(), span: None }
+    }
+
+    /// The location this error points at, if it has one.
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            PackardError::Yaml { span, .. }
+            | PackardError::Syntax { span, .. }
+            | PackardError::SceneNotFound { span, .. }
+            | PackardError::ChoiceUnavailable { span, .. }
+            | PackardError::UndefinedVariable { span, .. }
+            | PackardError::Runtime { span, .. } => span.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Attach a location to an error that does not have one yet.
+    pub fn with_span(mut self, new_span: &Span) -> Self {
+        match &mut self {
+            PackardError::Yaml { span, .. }
+            | PackardError::Syntax { span, .. }
+            | PackardError::SceneNotFound { span, .. }
+            | PackardError::ChoiceUnavailable { span, .. }
+            | PackardError::UndefinedVariable { span, .. }
+            | PackardError::Runtime { span, .. } if span.is_none() => {
+                *span = Some(new_span.clone());
+            }
+            _ => {}
+        }
+        self
+    }
+
+    /// The error message without any location.
+    pub fn message(&self) -> String {
+        match self {
+            PackardError::Io { path, source } => format!("failed to read {}: {}", path.display(), source),
+            PackardError::InvalidVault { path, message } => format!("{}: {}", path.display(), message),
+            PackardError::Yaml { source, .. } => format!("invalid frontmatter: {}", source),
+            PackardError::Syntax { message, .. } => message.clone(),
+            PackardError::SceneNotFound { id, .. } => format!("scene '{}' not found", id),
+            PackardError::InvalidChoice { index } => format!("invalid choice: {}", index),
+            PackardError::ChoiceNotFound { id } => format!("no choice with id '{}' in this scene", id),
+            PackardError::ChoiceUnavailable { id, .. } => format!("choice '{}' is not available", id),
+            PackardError::UndefinedVariable { name, .. } => format!("variable '{}' is not defined", name),
+            PackardError::Runtime { message, .. } => message.clone(),
+            PackardError::InvalidSave { path: Some(path), message } => {
+                format!("invalid save file {}: {}", path.display(), message)
+            }
+            PackardError::InvalidSave { path: None, message } => format!("invalid save file: {}", message),
+        }
+    }
+
+    /// Render the error rustc-style, with a snippet of `source` (the full text
+    /// of the file the span points into) underlining the offending markup.
+    pub fn render(&self, source: Option<&str>) -> String {
+        render_snippet("error", &self.message(), self.span(), source)
+    }
+
+    /// Like [`PackardError::render`], reading the snippet from the file on disk.
+    pub fn render_from_disk(&self) -> String {
+        let source = self.span().and_then(|s| std::fs::read_to_string(&s.file).ok());
+        self.render(source.as_deref())
+    }
+}
+
+impl fmt::Display for PackardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.span() {
+            Some(span) => write!(f, "{}:{}:{}: {}", span.file, span.line, span.column, self.message()),
+            None => write!(f, "{}", self.message()),
+        }
+    }
+}
+
+impl std::error::Error for PackardError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PackardError::Io { source, .. } => Some(source),
+            PackardError::Yaml { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Format a message the way rustc does:
+///
+/// ```text
+/// error: invalid effect syntax: oops
+///   --> investigate.md:15:22
+///    |
+/// 15 | [[key|Take the key]](oops)
+///    |                      ^^^^
+/// ```
+pub fn render_snippet(level: &str, message: &str, span: Option<&Span>, source: Option<&str>) -> String {
+    let mut out = format!("{}: {}", level, message);
+    let span = match span {
+        Some(span) => span,
+        None => return out,
+    };
+
+    let gutter = span.line.to_string().len();
+    out.push_str(&format!("\n{}--> {}:{}:{}", " ".repeat(gutter), span.file, span.line, span.column));
+
+    let source = match source {
+        Some(source) if span.start <= source.len() => source,
+        _ => return out,
+    };
+    let line_text = match source.lines().nth(span.line - 1) {
+        Some(line_text) => line_text,
+        None => return out,
+    };
+
+    // Underline the span, stopping at the end of its first line
+    let line_start = source[..span.start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let visible_end = span.end.min(line_start + line_text.len()).max(span.start);
+    let width = source[span.start..visible_end].chars().count().max(1);
+
+    out.push_str(&format!("\n{} |", " ".repeat(gutter)));
+    out.push_str(&format!("\n{} | {}", span.line, line_text));
+    out.push_str(&format!(
+        "\n{} | {}{}",
+        " ".repeat(gutter),
+        " ".repeat(span.column - 1),
+        "^".repeat(width)
+    ));
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Source;
+
+    #[test]
+    fn test_render_snippet() {
+        let text = "---\ntitle: A\n---\n[[key|Take]](oops)\n";
+        let start = text.find("oops").unwrap();
+        let span = Source::new("a.md", text).span(start, start + 4);
+
+        let error = PackardError::syntax("invalid effect syntax: oops").with_span(&span);
+        assert_eq!(error.to_string(), "a.md:4:14: invalid effect syntax: oops");
+        assert_eq!(
+            error.render(Some(text)),
+            "error: invalid effect syntax: oops\n --> a.md:4:14\n  |\n4 | [[key|Take]](oops)\n  |              ^^^^"
+        );
+    }
+
+    #[test]
+    fn test_with_span_keeps_existing_span() {
+        let text = "one\ntwo\n";
+        let source = Source::new("a.md", text);
+        let error = PackardError::syntax("bad")
+            .with_span(&source.span(4, 7))
+            .with_span(&source.span(0, 3));
+        assert_eq!(error.span().unwrap().line, 2);
+    }
+}
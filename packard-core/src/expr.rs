@@ -0,0 +1,876 @@
+//! Expressions shared by conditions and effects: literals, variables,
+//! arithmetic, string concatenation, lists and built-in functions.
+
+use std::collections::HashMap;
+use std::fmt;
+use serde_yaml::Value;
+use crate::effects::State;
+use crate::error::{PackardError, Result};
+use crate::rng::Rng;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Value),
+    Variable(String),
+    /// `["key", "lamp"]`
+    List(Vec<Expr>),
+    Neg(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    /// Membership: `"key" in player.inventory`
+    In,
+}
+
+impl BinaryOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::In => "in",
+        }
+    }
+}
+
+/// Built-in functions with their minimum and maximum argument counts.
+const FUNCTIONS: &[(&str, usize, usize)] = &[
+    ("min", 1, usize::MAX),
+    ("max", 1, usize::MAX),
+    ("clamp", 3, 3),
+    ("random", 2, 2),
+    ("chance", 1, 1),
+    ("has", 2, 2),
+    ("count", 1, 1),
+    ("visited", 1, 1),
+    ("visits", 1, 1),
+    ("turns", 0, 0),
+];
+
+impl Expr {
+    pub fn eval(&self, state: &State, rng: &mut Rng) -> Result<Value> {
+        self.eval_like(state, rng, None)
+    }
+
+    /// Evaluate, letting a missing variable default to a value shaped like `like`.
+    pub(crate) fn eval_like(&self, state: &State, rng: &mut Rng, like: Option<&Value>) -> Result<Value> {
+        match self {
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Variable(name) => state.read(name, like),
+            Expr::List(items) => {
+                let mut values = Vec::with_capacity(items.len());
+                for item in items {
+                    values.push(item.eval(state, rng)?);
+                }
+                Ok(Value::Sequence(values))
+            }
+            Expr::Neg(inner) => match as_number(&inner.eval(state, rng)?) {
+                Some(Number::Int(n)) => n
+                    .checked_neg()
+                    .map(|n| Value::Number(n.into()))
+                    .ok_or_else(|| PackardError::runtime(format!("Overflow negating {}", inner))),
+                Some(Number::Float(f)) => Ok(Value::Number((-f).into())),
+                None => Err(PackardError::runtime(format!("Cannot negate {}: not a number", inner))),
+            },
+            Expr::Binary(BinaryOp::In, item, list) => {
+                let item = item.eval(state, rng)?;
+                let list = list.eval_like(state, rng, Some(&empty_list()))?;
+                binary(BinaryOp::In, &item, &list)
+            }
+            Expr::Binary(op, left, right) => {
+                let (left, right) = eval_pair(left, right, state, rng)?;
+                binary(*op, &left, &right)
+            }
+            Expr::Call(name, args) => {
+                let mut values = Vec::with_capacity(args.len());
+                for (i, arg) in args.iter().enumerate() {
+                    // A list that was never written is simply empty.
+                    let like = (i == 0 && matches!(name.as_str(), "has" | "count")).then(empty_list);
+                    values.push(arg.eval_like(state, rng, like.as_ref())?);
+                }
+                call(name, &values, state, rng)
+            }
+        }
+    }
+
+    fn is_undefined(&self, state: &State) -> bool {
+        matches!(self, Expr::Variable(name) if state.get(name).is_none())
+    }
+
+    /// Names of the state variables this expression reads.
+    pub fn variables(&self) -> Vec<&str> {
+        match self {
+            Expr::Literal(_) => Vec::new(),
+            Expr::Variable(name) => vec![name.as_str()],
+            Expr::List(items) => items.iter().flat_map(|i| i.variables()).collect(),
+            Expr::Neg(inner) => inner.variables(),
+            Expr::Binary(_, left, right) => {
+                let mut variables = left.variables();
+                variables.extend(right.variables());
+                variables
+            }
+            Expr::Call(_, args) => args.iter().flat_map(|a| a.variables()).collect(),
+        }
+    }
+
+    /// Scene names written as literals in `visited(...)` and `visits(...)`.
+    pub fn scene_names(&self) -> Vec<&str> {
+        match self {
+            Expr::Literal(_) | Expr::Variable(_) => Vec::new(),
+            Expr::Call(name, args) if is_visit(name) => match args.as_slice() {
+                [Expr::Literal(Value::String(scene))] => vec![scene.as_str()],
+                _ => args.iter().flat_map(|a| a.scene_names()).collect(),
+            },
+            Expr::Call(_, items) | Expr::List(items) => items.iter().flat_map(|i| i.scene_names()).collect(),
+            Expr::Neg(inner) => inner.scene_names(),
+            Expr::Binary(_, left, right) => {
+                let mut names = left.scene_names();
+                names.extend(right.scene_names());
+                names
+            }
+        }
+    }
+
+    /// Replace the names from [`Expr::scene_names`] with the scene ids
+    /// they resolve to.
+    pub(crate) fn rename_scenes(&mut self, ids: &HashMap<String, String>) {
+        match self {
+            Expr::Literal(_) | Expr::Variable(_) => {}
+            Expr::Call(name, args) if is_visit(name) => {
+                if let [Expr::Literal(Value::String(scene))] = args.as_mut_slice() {
+                    if let Some(id) = ids.get(scene.as_str()) {
+                        *scene = id.clone();
+                    }
+                }
+            }
+            Expr::Call(_, items) | Expr::List(items) => items.iter_mut().for_each(|i| i.rename_scenes(ids)),
+            Expr::Neg(inner) => inner.rename_scenes(ids),
+            Expr::Binary(_, left, right) => {
+                left.rename_scenes(ids);
+                right.rename_scenes(ids);
+            }
+        }
+    }
+}
+
+fn is_visit(function: &str) -> bool {
+    matches!(function, "visited" | "visits")
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Literal(value) => write!(f, "{}", format_value(value)),
+            Expr::Variable(name) => write!(f, "{}", name),
+            Expr::List(items) => {
+                let items: Vec<_> = items.iter().map(|i| i.to_string()).collect();
+                write!(f, "[{}]", items.join(", "))
+            }
+            Expr::Neg(inner) => write!(f, "-{}", inner),
+            Expr::Binary(op, left, right) => write!(f, "{} {} {}", left, op.symbol(), right),
+            Expr::Call(name, args) if args.is_empty() && name == "turns" => write!(f, "turns"),
+            Expr::Call(name, args) => {
+                let args: Vec<_> = args.iter().map(|a| a.to_string()).collect();
+                write!(f, "{}({})", name, args.join(", "))
+            }
+        }
+    }
+}
+
+/// Evaluate both operands of a binary operation. When one side is a variable
+/// that was never set, it defaults to match the other side's type.
+pub(crate) fn eval_pair(left: &Expr, right: &Expr, state: &State, rng: &mut Rng) -> Result<(Value, Value)> {
+    if left.is_undefined(state) {
+        let right = right.eval(state, rng)?;
+        Ok((left.eval_like(state, rng, Some(&right))?, right))
+    } else {
+        let left = left.eval(state, rng)?;
+        let right = right.eval_like(state, rng, Some(&left))?;
+        Ok((left, right))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+fn as_number(value: &Value) -> Option<Number> {
+    match value {
+        Value::Number(n) => n.as_i64().map(Number::Int).or_else(|| n.as_f64().map(Number::Float)),
+        _ => None,
+    }
+}
+
+fn number_value(n: Number) -> Value {
+    match n {
+        Number::Int(i) => Value::Number(i.into()),
+        Number::Float(f) => Value::Number(f.into()),
+    }
+}
+
+fn as_float(n: Number) -> f64 {
+    match n {
+        Number::Int(i) => i as f64,
+        Number::Float(f) => f,
+    }
+}
+
+pub(crate) fn empty_list() -> Value {
+    Value::Sequence(Vec::new())
+}
+
+/// Apply an operator. On a list `+` appends (or joins two lists) and `-`
+/// removes one occurrence (or each item of a list); `in` tests membership
+/// in a list or a substring. Otherwise `+` concatenates when either side is
+/// a string and everything else needs two numbers. Integer division truncates.
+pub(crate) fn binary(op: BinaryOp, left: &Value, right: &Value) -> Result<Value> {
+    match (op, left, right) {
+        (BinaryOp::Add, Value::Sequence(list), Value::Sequence(more)) => {
+            return Ok(Value::Sequence(list.iter().chain(more).cloned().collect()))
+        }
+        (BinaryOp::Add, Value::Sequence(list), item) => {
+            let mut list = list.clone();
+            list.push(item.clone());
+            return Ok(Value::Sequence(list));
+        }
+        (BinaryOp::Sub, Value::Sequence(list), Value::Sequence(items)) => {
+            let mut list = list.clone();
+            for item in items {
+                remove_one(&mut list, item);
+            }
+            return Ok(Value::Sequence(list));
+        }
+        (BinaryOp::Sub, Value::Sequence(list), item) => {
+            let mut list = list.clone();
+            remove_one(&mut list, item);
+            return Ok(Value::Sequence(list));
+        }
+        (BinaryOp::In, item, Value::Sequence(list)) => return Ok(Value::Bool(list.contains(item))),
+        (BinaryOp::In, Value::String(needle), Value::String(haystack)) => {
+            return Ok(Value::Bool(haystack.contains(needle.as_str())))
+        }
+        _ => {}
+    }
+
+    if op == BinaryOp::Add && (left.is_string() || right.is_string()) {
+        if let (Some(l), Some(r)) = (concat_text(left), concat_text(right)) {
+            return Ok(Value::String(l + &r));
+        }
+    }
+
+    let mismatch = || {
+        PackardError::runtime(format!(
+            "Cannot apply '{}' to {} and {}",
+            op.symbol(),
+            type_name(left),
+            type_name(right)
+        ))
+    };
+    let (l, r) = match (as_number(left), as_number(right)) {
+        (Some(l), Some(r)) if op != BinaryOp::In => (l, r),
+        _ => return Err(mismatch()),
+    };
+
+    let result = match (l, r) {
+        (Number::Int(l), Number::Int(r)) => {
+            if r == 0 && matches!(op, BinaryOp::Div | BinaryOp::Rem) {
+                return Err(PackardError::runtime("Division by zero"));
+            }
+            let result = match op {
+                BinaryOp::Add => l.checked_add(r),
+                BinaryOp::Sub => l.checked_sub(r),
+                BinaryOp::Mul => l.checked_mul(r),
+                BinaryOp::Div => l.checked_div(r),
+                BinaryOp::Rem => l.checked_rem(r),
+                BinaryOp::In => unreachable!(),
+            };
+            Number::Int(result.ok_or_else(|| {
+                PackardError::runtime(format!("Overflow computing {} {} {}", l, op.symbol(), r))
+            })?)
+        }
+        (l, r) => {
+            let (l, r) = (as_float(l), as_float(r));
+            if r == 0.0 && matches!(op, BinaryOp::Div | BinaryOp::Rem) {
+                return Err(PackardError::runtime("Division by zero"));
+            }
+            Number::Float(match op {
+                BinaryOp::Add => l + r,
+                BinaryOp::Sub => l - r,
+                BinaryOp::Mul => l * r,
+                BinaryOp::Div => l / r,
+                BinaryOp::Rem => l % r,
+                BinaryOp::In => unreachable!(),
+            })
+        }
+    };
+
+    Ok(number_value(result))
+}
+
+fn remove_one(list: &mut Vec<Value>, item: &Value) {
+    if let Some(pos) = list.iter().position(|v| v == item) {
+        list.remove(pos);
+    }
+}
+
+fn concat_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+fn call(name: &str, args: &[Value], state: &State, rng: &mut Rng) -> Result<Value> {
+    match (name, args) {
+        ("turns", []) => return Ok(Value::Number(state.turns.into())),
+        ("visits", [Value::String(id)]) => return Ok(Value::Number(state.visits(id).into())),
+        ("visited", [Value::String(id)]) => return Ok(Value::Bool(state.visits(id) > 0)),
+        ("visits" | "visited", [other]) => {
+            return Err(PackardError::runtime(format!(
+                "{}() expects a scene id string, got {}",
+                name,
+                type_name(other)
+            )))
+        }
+        ("has", [Value::Sequence(list), item]) => return Ok(Value::Bool(list.contains(item))),
+        ("count", [Value::Sequence(list)]) => return Ok(Value::Number((list.len() as i64).into())),
+        ("has" | "count", [other, ..]) => {
+            return Err(PackardError::runtime(format!("{}() expects a list, got {}", name, type_name(other))))
+        }
+        _ => {}
+    }
+
+    let numbers = args
+        .iter()
+        .map(|a| {
+            as_number(a).ok_or_else(|| {
+                PackardError::runtime(format!("{}() expects numbers, got {}", name, type_name(a)))
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let pick = |prefer_greater: bool| {
+        numbers
+            .iter()
+            .copied()
+            .reduce(|a, b| {
+                let greater = as_float(b) > as_float(a);
+                if greater == prefer_greater { b } else { a }
+            })
+            .map(number_value)
+            .unwrap()
+    };
+
+    match name {
+        "min" => Ok(pick(false)),
+        "max" => Ok(pick(true)),
+        "clamp" => {
+            let (x, lo, hi) = (numbers[0], numbers[1], numbers[2]);
+            let clamped = if as_float(x) < as_float(lo) {
+                lo
+            } else if as_float(x) > as_float(hi) {
+                hi
+            } else {
+                x
+            };
+            Ok(number_value(clamped))
+        }
+        "random" => match (numbers[0], numbers[1]) {
+            (Number::Int(min), Number::Int(max)) => Ok(Value::Number(rng.range(min, max).into())),
+            _ => Err(PackardError::runtime("random() expects whole numbers")),
+        },
+        "chance" => Ok(Value::Bool(rng.chance(as_float(numbers[0])))),
+        _ => Err(PackardError::runtime(format!("Unknown function: {}", name))),
+    }
+}
+
+pub(crate) fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Sequence(_) => "list",
+        Value::Mapping(_) => "map",
+        Value::Tagged(_) => "tagged value",
+    }
+}
+
+/// Format a state value the way it would be written in an expression,
+/// e.g. `"key"` or `["key", "lamp"]`.
+pub fn format_value(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => format!("\"{}\"", s),
+        Value::Sequence(items) => {
+            let items: Vec<_> = items.iter().map(format_value).collect();
+            format!("[{}]", items.join(", "))
+        }
+        other => format!("{:?}", other),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Token {
+    Ident(String),
+    Number(String),
+    Str(String),
+    Compare(&'static str),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Comma,
+    LBracket,
+    RBracket,
+    In,
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        let symbol = match self {
+            Token::Ident(s) | Token::Number(s) => return format!("'{}'", s),
+            Token::Str(s) => return format!("\"{}\"", s),
+            Token::Compare(op) => op,
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Star => "*",
+            Token::Slash => "/",
+            Token::Percent => "%",
+            Token::Comma => ",",
+            Token::LBracket => "[",
+            Token::RBracket => "]",
+            Token::In => "in",
+            Token::And => "AND",
+            Token::Or => "OR",
+            Token::Not => "NOT",
+            Token::LParen => "(",
+            Token::RParen => ")",
+        };
+        format!("'{}'", symbol)
+    }
+}
+
+/// Split condition or effect text into tokens. `what` names the kind of
+/// markup in error messages.
+pub(crate) fn tokenize(input: &str, what: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = input.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let two: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+        if let Some(op) = [">=", "<=", "==", "!="].into_iter().find(|op| *op == two) {
+            tokens.push(Token::Compare(op));
+            i += 2;
+            continue;
+        }
+        if two == "&&" || two == "||" {
+            tokens.push(if two == "&&" { Token::And } else { Token::Or });
+            i += 2;
+            continue;
+        }
+
+        match c {
+            '>' => tokens.push(Token::Compare(">")),
+            '<' => tokens.push(Token::Compare("<")),
+            '!' => tokens.push(Token::Not),
+            '(' => tokens.push(Token::LParen),
+            ')' => tokens.push(Token::RParen),
+            ',' => tokens.push(Token::Comma),
+            '[' => tokens.push(Token::LBracket),
+            ']' => tokens.push(Token::RBracket),
+            '+' => tokens.push(Token::Plus),
+            '-' => tokens.push(Token::Minus),
+            '*' => tokens.push(Token::Star),
+            '/' => tokens.push(Token::Slash),
+            '%' => tokens.push(Token::Percent),
+            '"' | '\'' => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|&ch| ch == c)
+                    .map(|p| i + 1 + p)
+                    .ok_or_else(|| PackardError::syntax(format!("Unterminated string in {}: {}", what, input)))?;
+                tokens.push(Token::Str(chars[i + 1..end].iter().collect()));
+                i = end + 1;
+                continue;
+            }
+            _ if c.is_ascii_digit() => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                tokens.push(Token::Number(chars[start..i].iter().collect()));
+                continue;
+            }
+            _ if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.') {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                tokens.push(match word.as_str() {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    "in" => Token::In,
+                    _ => Token::Ident(word),
+                });
+                continue;
+            }
+            _ => {
+                return Err(PackardError::syntax(format!(
+                    "Unexpected character '{}' in {}: {}",
+                    c, what, input
+                )))
+            }
+        }
+        i += 1;
+    }
+
+    Ok(tokens)
+}
+
+/// Recursive-descent parser over a token stream. Expressions follow:
+///
+/// ```text
+/// expr    := sum ("in" sum)?
+/// sum     := term (("+" | "-") term)*
+/// term    := unary (("*" | "/" | "%") unary)*
+/// unary   := "-" unary | primary
+/// primary := number | string | true | false | null
+///          | name "(" (expr ("," expr)*)? ")" | variable | "(" expr ")"
+///          | "[" (expr ("," expr)*)? "]"
+/// ```
+pub(crate) struct Parser<'a> {
+    pub input: &'a str,
+    pub what: &'static str,
+    pub tokens: Vec<Token>,
+    pub pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(input: &'a str, what: &'static str) -> Result<Self> {
+        Ok(Parser {
+            input: input.trim(),
+            what,
+            tokens: tokenize(input, what)?,
+            pos: 0,
+        })
+    }
+
+    pub fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    pub fn error(&self, expected: &str) -> PackardError {
+        let found = match self.tokens.get(self.pos) {
+            Some(token) => token.describe(),
+            None => format!("end of {}", self.what),
+        };
+        PackardError::syntax(format!(
+            "Invalid {} syntax: expected {}, found {} in: {}",
+            self.what, expected, found, self.input
+        ))
+    }
+
+    /// Fail unless every token has been consumed.
+    pub fn expect_end(&self, expected: &str) -> Result<()> {
+        if self.pos < self.tokens.len() {
+            return Err(self.error(expected));
+        }
+        Ok(())
+    }
+
+    fn expect(&mut self, token: Token) -> Result<()> {
+        if self.peek() != Some(&token) {
+            return Err(self.error(&token.describe()));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    pub fn parse_expr(&mut self) -> Result<Expr> {
+        let left = self.parse_sum()?;
+        if self.peek() != Some(&Token::In) {
+            return Ok(left);
+        }
+        self.pos += 1;
+        let right = self.parse_sum()?;
+        Ok(Expr::Binary(BinaryOp::In, Box::new(left), Box::new(right)))
+    }
+
+    fn parse_sum(&mut self) -> Result<Expr> {
+        let mut left = self.parse_term()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Plus) => BinaryOp::Add,
+                Some(Token::Minus) => BinaryOp::Sub,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            let right = self.parse_term()?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn parse_term(&mut self) -> Result<Expr> {
+        let mut left = self.parse_unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Star) => BinaryOp::Mul,
+                Some(Token::Slash) => BinaryOp::Div,
+                Some(Token::Percent) => BinaryOp::Rem,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            let right = self.parse_unary()?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr> {
+        if self.peek() == Some(&Token::Minus) {
+            self.pos += 1;
+            return match self.parse_unary()? {
+                Expr::Literal(Value::Number(n)) if n.as_i64().is_some() => {
+                    Ok(Expr::Literal(Value::Number((-n.as_i64().unwrap()).into())))
+                }
+                inner => Ok(Expr::Neg(Box::new(inner))),
+            };
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr> {
+        let token = match self.peek() {
+            Some(token) => token.clone(),
+            None => return Err(self.error("a value")),
+        };
+
+        match token {
+            Token::Number(text) => {
+                let value = match text.parse::<i64>() {
+                    Ok(n) => Value::Number(n.into()),
+                    Err(_) => match text.parse::<f64>() {
+                        Ok(f) => Value::Number(f.into()),
+                        Err(_) => return Err(self.error("a number")),
+                    },
+                };
+                self.pos += 1;
+                Ok(Expr::Literal(value))
+            }
+            Token::Str(text) => {
+                self.pos += 1;
+                Ok(Expr::Literal(Value::String(text)))
+            }
+            Token::Ident(name) => {
+                self.pos += 1;
+                match name.as_str() {
+                    "true" => return Ok(Expr::Literal(Value::Bool(true))),
+                    "false" => return Ok(Expr::Literal(Value::Bool(false))),
+                    "null" => return Ok(Expr::Literal(Value::Null)),
+                    _ => {}
+                }
+                if self.peek() == Some(&Token::LParen) {
+                    return self.parse_call(name);
+                }
+                if name == "turns" {
+                    return Ok(Expr::Call(name, Vec::new()));
+                }
+                Ok(Expr::Variable(name))
+            }
+            Token::LParen => {
+                self.pos += 1;
+                let inner = self.parse_expr()?;
+                self.expect(Token::RParen)?;
+                Ok(inner)
+            }
+            Token::LBracket => {
+                self.pos += 1;
+                let items = self.parse_args(Token::RBracket)?;
+                Ok(Expr::List(items))
+            }
+            _ => Err(self.error("a value")),
+        }
+    }
+
+    /// Comma-separated expressions up to and including `close`.
+    fn parse_args(&mut self, close: Token) -> Result<Vec<Expr>> {
+        let mut args = Vec::new();
+        if self.peek() != Some(&close) {
+            loop {
+                args.push(self.parse_expr()?);
+                if self.peek() != Some(&Token::Comma) {
+                    break;
+                }
+                self.pos += 1;
+            }
+        }
+        self.expect(close)?;
+        Ok(args)
+    }
+
+    fn parse_call(&mut self, name: String) -> Result<Expr> {
+        self.expect(Token::LParen)?;
+        let args = self.parse_args(Token::RParen)?;
+
+        match FUNCTIONS.iter().find(|(f, _, _)| *f == name) {
+            None => Err(PackardError::syntax(format!(
+                "Unknown function '{}' in {}: {}",
+                name, self.what, self.input
+            ))),
+            Some((_, min, max)) if args.len() < *min || args.len() > *max => Err(PackardError::syntax(format!(
+                "Wrong number of arguments to {}() in {}: {}",
+                name, self.what, self.input
+            ))),
+            Some(_) => Ok(Expr::Call(name, args)),
+        }
+    }
+}
+
+/// Parse a standalone expression, e.g. the right-hand side of an effect.
+pub fn parse_expr(input: &str) -> Result<Expr> {
+    let mut parser = Parser::new(input, "expression")?;
+    let expr = parser.parse_expr()?;
+    parser.expect_end("an operator or end of expression")?;
+    Ok(expr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(input: &str, state: &State) -> Result<Value> {
+        parse_expr(input)?.eval(state, &mut Rng::new(1))
+    }
+
+    fn int(n: i64) -> Value {
+        Value::Number(n.into())
+    }
+
+    #[test]
+    fn test_precedence_and_parentheses() {
+        let state = State::new();
+        assert_eq!(eval("1 + 2 * 3", &state).unwrap(), int(7));
+        assert_eq!(eval("(1 + 2) * 3", &state).unwrap(), int(9));
+        assert_eq!(eval("10 - 4 - 3", &state).unwrap(), int(3));
+        assert_eq!(eval("7 / 2 + 7 % 2", &state).unwrap(), int(4));
+        assert_eq!(eval("-2 * -(3)", &state).unwrap(), int(6));
+        assert_eq!(eval("1.5 * 2", &state).unwrap(), Value::Number(3.0.into()));
+    }
+
+    #[test]
+    fn test_variables() {
+        let mut state = State::new();
+        state.set("player.gold", int(50));
+        state.set("item.price", int(12));
+        assert_eq!(eval("player.gold - item.price * 2", &state).unwrap(), int(26));
+        assert_eq!(eval("missing + 1", &state).unwrap(), int(1));
+        assert_eq!(
+            parse_expr("player.gold - item.price * 2").unwrap().variables(),
+            vec!["player.gold", "item.price"]
+        );
+    }
+
+    #[test]
+    fn test_string_concatenation() {
+        let mut state = State::new();
+        state.set("name", Value::String("Ada".to_string()));
+        assert_eq!(eval("name + \" the Brave\"", &state).unwrap(), Value::String("Ada the Brave".to_string()));
+        assert_eq!(eval("\"Level \" + 3", &state).unwrap(), Value::String("Level 3".to_string()));
+        assert_eq!(eval("title + \"!\"", &state).unwrap(), Value::String("!".to_string()));
+    }
+
+    #[test]
+    fn test_builtins() {
+        let mut state = State::new();
+        state.set("player.hp", int(98));
+        assert_eq!(eval("clamp(player.hp + 5, 0, 100)", &state).unwrap(), int(100));
+        assert_eq!(eval("clamp(-5, 0, 100)", &state).unwrap(), int(0));
+        assert_eq!(eval("min(4, 2, 8)", &state).unwrap(), int(2));
+        assert_eq!(eval("max(4, 2, 8)", &state).unwrap(), int(8));
+
+        let roll = eval("random(1, 6)", &state).unwrap().as_i64().unwrap();
+        assert!((1..=6).contains(&roll));
+        assert_eq!(eval("chance(100)", &state).unwrap(), Value::Bool(true));
+        assert_eq!(eval("chance(0)", &state).unwrap(), Value::Bool(false));
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let mut state = State::new();
+        state.set("flag", Value::Bool(true));
+        assert!(eval("1 / 0", &state).is_err());
+        assert!(eval("flag * 2", &state).is_err());
+        assert!(eval("-flag", &state).is_err());
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        for input in ["", "1 +", "(1 + 2", "1 2", "unknown(1)", "clamp(1, 2)", "a = 1", "\"open"] {
+            assert!(parse_expr(input).is_err(), "{} should not parse", input);
+        }
+    }
+
+    #[test]
+    fn test_lists() {
+        let mut state = State::new();
+        state.set("inv", Value::Sequence(vec![Value::String("key".to_string())]));
+
+        assert_eq!(eval("count(inv + \"lamp\")", &state).unwrap(), int(2));
+        assert_eq!(eval("count(inv - \"key\")", &state).unwrap(), int(0));
+        assert_eq!(eval("count([1, 2] + [3])", &state).unwrap(), int(3));
+        assert_eq!(eval("has(inv, \"key\")", &state).unwrap(), Value::Bool(true));
+        assert_eq!(eval("\"lamp\" in inv", &state).unwrap(), Value::Bool(false));
+        assert_eq!(eval("\"ey\" in \"key\"", &state).unwrap(), Value::Bool(true));
+        assert_eq!(eval("count(missing)", &state).unwrap(), int(0));
+        assert_eq!(eval("\"key\" in missing", &state).unwrap(), Value::Bool(false));
+        assert!(eval("count(5)", &state).is_err());
+        assert_eq!(format_value(&eval("inv + [1, true]", &state).unwrap()), "[\"key\", 1, true]");
+    }
+
+    #[test]
+    fn test_visit_functions() {
+        let mut state = State::new();
+        state.record_visit("journal");
+        state.record_visit("journal");
+        state.turns = 3;
+
+        assert_eq!(eval("visits(\"journal\") + turns", &state).unwrap(), int(5));
+        assert_eq!(eval("visited(\"journal\")", &state).unwrap(), Value::Bool(true));
+        assert_eq!(eval("visited(\"secret\")", &state).unwrap(), Value::Bool(false));
+        assert_eq!(parse_expr("turns + 1").unwrap().to_string(), "turns + 1");
+        assert!(parse_expr("turns + 1").unwrap().variables().is_empty());
+        assert!(eval("visits(3)", &state).is_err());
+    }
+}
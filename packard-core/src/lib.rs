@@ -1,15 +1,33 @@
 pub mod vault;
 pub mod scene;
 pub mod character;
+pub mod entity;
 pub mod effects;
 pub mod conditions;
 pub mod dialogue;
 pub mod runtime;
+pub mod parser;
+pub mod error;
+pub mod check;
+pub mod save;
+pub mod expr;
+pub mod rng;
+pub mod template;
+pub mod manifest;
 
 pub use vault::Vault;
 pub use scene::Scene;
 pub use character::Character;
-pub use effects::{State, Effect};
+pub use entity::Entity;
+pub use effects::{State, Effect, Change, UndefinedPolicy};
 pub use conditions::Condition;
 pub use dialogue::{DialogueLine};
-pub use runtime::Runtime;
+pub use runtime::{Runtime, ChoiceOutcome};
+pub use parser::Span;
+pub use error::PackardError;
+pub use check::{Diagnostic, Severity};
+pub use save::SaveFile;
+pub use expr::Expr;
+pub use rng::Rng;
+pub use template::Template;
+pub use manifest::Manifest;
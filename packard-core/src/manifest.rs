@@ -0,0 +1,169 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Deserializer};
+use crate::effects::UndefinedPolicy;
+use crate::error::{PackardError, Result};
+use crate::parser::Source;
+
+/// File at the vault root that holds the manifest.
+pub const MANIFEST_FILE: &str = "packard.yaml";
+
+/// Scene a story starts in when the manifest does not name one.
+pub const DEFAULT_START_SCENE: &str = "start";
+
+/// Story settings from `packard.yaml`. Every field is optional; a vault
+/// without the file gets the defaults.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Manifest {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    #[serde(deserialize_with = "scalar_string")]
+    pub version: Option<String>,
+    /// Scene id the story starts in
+    pub start: String,
+    pub folders: Folders,
+    /// Variables set before the start scene is entered
+    pub variables: BTreeMap<String, serde_yaml::Value>,
+    /// `default` or `strict`, see [`UndefinedPolicy`]
+    pub undefined_variables: UndefinedPolicy,
+}
+
+/// Folders with special meaning. A folder given by name alone, like the
+/// default `characters`, counts wherever it is, e.g. `act1/characters`; one
+/// given as a path, like `story/cast`, is relative to the vault root.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Folders {
+    pub characters: PathBuf,
+    pub items: PathBuf,
+    /// Skipped entirely, e.g. `templates` or `.trash`. `.obsidian` always is.
+    pub ignore: Vec<PathBuf>,
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Manifest {
+            title: None,
+            author: None,
+            version: None,
+            start: DEFAULT_START_SCENE.to_string(),
+            folders: Folders::default(),
+            variables: BTreeMap::new(),
+            undefined_variables: UndefinedPolicy::default(),
+        }
+    }
+}
+
+impl Default for Folders {
+    fn default() -> Self {
+        Folders {
+            characters: PathBuf::from("characters"),
+            items: PathBuf::from("items"),
+            ignore: Vec::new(),
+        }
+    }
+}
+
+impl Manifest {
+    /// Read `packard.yaml` from the vault root, or the defaults if there is none.
+    pub fn load(vault_path: &Path) -> Result<Self> {
+        let path = vault_path.join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(Manifest::default());
+        }
+
+        let text = fs::read_to_string(&path).map_err(|source| PackardError::Io { path: path.clone(), source })?;
+        let file = path.display().to_string();
+        Manifest::parse(&Source::new(&file, &text))
+    }
+
+    pub fn parse(source: &Source) -> Result<Self> {
+        if source.text.trim().is_empty() {
+            return Ok(Manifest::default());
+        }
+        serde_yaml::from_str(source.text).map_err(|e| {
+            let start = e.location().map(|l| l.index()).unwrap_or(0);
+            PackardError::Yaml { span: Some(source.span(start, start)), source: e }
+        })
+    }
+
+    /// Whether a note at `relative` (to the vault root) is in `folder`, see
+    /// [`Folders`].
+    pub fn in_folder(relative: &Path, folder: &Path) -> bool {
+        match folder.components().count() {
+            1 => relative.parent().is_some_and(|dir| dir.components().any(|c| c.as_os_str() == folder.as_os_str())),
+            _ => relative.starts_with(folder),
+        }
+    }
+
+    /// Whether a note at `relative` (to the vault root) should be skipped.
+    pub fn is_ignored(&self, relative: &Path) -> bool {
+        relative.components().any(|c| c.as_os_str() == ".obsidian")
+            || self.folders.ignore.iter().any(|folder| relative.starts_with(folder))
+    }
+}
+
+/// `version: 1.2` is a number to YAML; take it as written.
+fn scalar_string<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<String>, D::Error> {
+    Ok(match Option::<serde_yaml::Value>::deserialize(deserializer)? {
+        Some(serde_yaml::Value::String(s)) => Some(s),
+        Some(serde_yaml::Value::Number(n)) => Some(n.to_string()),
+        Some(serde_yaml::Value::Bool(b)) => Some(b.to_string()),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<Manifest> {
+        Manifest::parse(&Source::new("packard.yaml", text))
+    }
+
+    #[test]
+    fn test_defaults() {
+        let manifest = parse("").unwrap();
+        assert_eq!(manifest, Manifest::default());
+        assert_eq!(manifest.start, "start");
+        assert!(manifest.is_ignored(Path::new(".obsidian/workspace.md")));
+        assert!(!manifest.is_ignored(Path::new("templates/scene.md")));
+    }
+
+    #[test]
+    fn test_full_manifest() {
+        let manifest = parse(
+            "title: The Keeper's House\nauthor: Ada\nversion: 1.2\nstart: intro\n\
+             folders:\n  characters: cast\n  ignore: [templates, .trash]\n\
+             variables:\n  player.gold: 10\nundefined_variables: strict\n",
+        )
+        .unwrap();
+        assert_eq!(manifest.title.as_deref(), Some("The Keeper's House"));
+        assert_eq!(manifest.version.as_deref(), Some("1.2"));
+        assert_eq!(manifest.start, "intro");
+        assert_eq!(manifest.folders.characters, PathBuf::from("cast"));
+        assert_eq!(manifest.folders.items, PathBuf::from("items"));
+        assert!(manifest.is_ignored(Path::new("templates/scene.md")));
+        assert_eq!(manifest.variables["player.gold"].as_i64(), Some(10));
+        assert_eq!(manifest.undefined_variables, UndefinedPolicy::Strict);
+    }
+
+    #[test]
+    fn test_folders_by_name_match_at_any_depth() {
+        let folder = |f: &str| PathBuf::from(f);
+        assert!(Manifest::in_folder(Path::new("characters/ada.md"), &folder("characters")));
+        assert!(Manifest::in_folder(Path::new("cast/characters/ada.md"), &folder("characters")));
+        assert!(!Manifest::in_folder(Path::new("characters.md"), &folder("characters")));
+        assert!(Manifest::in_folder(Path::new("story/cast/ada.md"), &folder("story/cast")));
+        assert!(!Manifest::in_folder(Path::new("old/story/cast/ada.md"), &folder("story/cast")));
+    }
+
+    #[test]
+    fn test_errors_point_into_the_file() {
+        let err = parse("title: Story\nstrat: intro\n").unwrap_err();
+        assert_eq!(err.span().unwrap().line, 2);
+        assert!(parse("start: [oops").is_err());
+    }
+}
@@ -0,0 +1,628 @@
+//! Markdown-aware scanner for scene markup.
+//!
+//! Walks a note line by line, skipping fenced code blocks, inline code,
+//! callouts and comments, and yields every choice link exactly once in
+//! source order together with the location of its condition and effects.
+
+use crate::error::{PackardError, Result};
+
+/// A location in a source file. Lines and columns are 1-based; `start` and
+/// `end` are byte offsets into the whole file (frontmatter included).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A piece of markup text (a condition or an effect list) and where it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fragment {
+    pub text: String,
+    pub span: Span,
+}
+
+/// A choice link as written in the note: `{if: condition}[[target|label]](effects)`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChoiceMarkup {
+    pub target: String,
+    /// Heading from a `[[target#Heading]]` link
+    pub heading: Option<String>,
+    /// Empty for a bare `[[target]]` link
+    pub label: String,
+    pub label_span: Span,
+    pub condition: Option<Fragment>,
+    pub effects: Option<Fragment>,
+    /// Explicit id from an Obsidian block reference after the link: `^take-key`
+    pub block_id: Option<String>,
+    /// `Some(true)` for a `{once}` prefix, `Some(false)` for `{sticky}`
+    pub once: Option<bool>,
+    pub span: Span,
+}
+
+/// A source file with a line index for turning byte offsets into spans.
+pub struct Source<'a> {
+    pub file: &'a str,
+    pub text: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> Source<'a> {
+    pub fn new(file: &'a str, text: &'a str) -> Self {
+        let mut line_starts = vec![0];
+        for (i, b) in text.bytes().enumerate() {
+            if b == b'\n' {
+                line_starts.push(i + 1);
+            }
+        }
+        Source { file, text, line_starts }
+    }
+
+    pub fn span(&self, start: usize, end: usize) -> Span {
+        let line = match self.line_starts.binary_search(&start) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        let column = self.text[self.line_starts[line]..start].chars().count() + 1;
+        Span {
+            file: self.file.to_string(),
+            line: line + 1,
+            column,
+            start,
+            end,
+        }
+    }
+}
+
+/// Split a note into its YAML frontmatter and body. The frontmatter must open
+/// on the very first line with `---` and close with a line holding only `---`.
+/// Returns the frontmatter (if any), the body and the byte offset of the body.
+pub fn split_frontmatter(content: &str) -> (Option<&str>, &str, usize) {
+    let first_line_end = content.find('\n').map(|i| i + 1).unwrap_or(content.len());
+    if content[..first_line_end].trim_end() != "---" {
+        return (None, content, 0);
+    }
+
+    let mut pos = first_line_end;
+    while pos < content.len() {
+        let line_end = content[pos..].find('\n').map(|i| pos + i + 1).unwrap_or(content.len());
+        if content[pos..line_end].trim_end() == "---" {
+            return (Some(&content[first_line_end..pos]), &content[line_end..], line_end);
+        }
+        pos = line_end;
+    }
+
+    (None, content, 0)
+}
+
+/// The span of a top-level `key: value` line in the frontmatter.
+pub fn frontmatter_key_span(source: &Source, frontmatter: &str, key: &str) -> Option<Span> {
+    let frontmatter_start = source.text.find('\n').map(|i| i + 1).unwrap_or(0);
+    let mut offset = frontmatter_start;
+    for line in frontmatter.split_inclusive('\n') {
+        let is_key = line
+            .strip_prefix(key)
+            .is_some_and(|rest| rest.trim_start().starts_with(':'));
+        if is_key {
+            return Some(source.span(offset, offset + line.trim_end().len()));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+/// Parse a note's frontmatter as YAML, pointing any error at its location in the file.
+pub fn parse_frontmatter(source: &Source, frontmatter: &str) -> Result<serde_yaml::Value> {
+    serde_yaml::from_str(frontmatter).map_err(|e| {
+        let frontmatter_start = source.text.find('\n').map(|i| i + 1).unwrap_or(0);
+        let start = frontmatter_start + e.location().map(|l| l.index()).unwrap_or(0);
+        PackardError::Yaml {
+            span: Some(source.span(start, start)),
+            source: e,
+        }
+    })
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Comment {
+    Html,
+    Obsidian,
+}
+
+impl Comment {
+    fn terminator(self) -> &'static str {
+        match self {
+            Comment::Html => "-->",
+            Comment::Obsidian => "%%",
+        }
+    }
+}
+
+/// A Markdown heading line, `## Text`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heading {
+    pub level: usize,
+    pub text: String,
+    /// Offset of the start of the heading line
+    pub start: usize,
+}
+
+/// Scan `source.text[body_start..end]` for ATX headings, skipping code blocks.
+pub fn scan_headings(source: &Source, body_start: usize, end: usize) -> Vec<Heading> {
+    let text = &source.text[..end];
+    let mut headings = Vec::new();
+    let mut fence: Option<(char, usize)> = None;
+
+    let mut line_start = body_start;
+    while line_start < text.len() {
+        let line_end = text[line_start..].find('\n').map(|i| line_start + i).unwrap_or(text.len());
+        let line = &text[line_start..line_end];
+        let indent = line.len() - line.trim_start_matches(' ').len();
+        let trimmed = line[indent..].trim_end();
+
+        if indent < 4 {
+            match fence {
+                Some((ch, len)) => {
+                    if fence_run(trimmed, ch) >= len && trimmed.trim_start_matches(ch).is_empty() {
+                        fence = None;
+                    }
+                }
+                None => {
+                    if let Some(ch) = ['`', '~'].into_iter().find(|c| trimmed.starts_with(*c)) {
+                        if fence_run(trimmed, ch) >= 3 {
+                            fence = Some((ch, fence_run(trimmed, ch)));
+                        }
+                    } else {
+                        let level = fence_run(trimmed, '#');
+                        let rest = &trimmed[level..];
+                        if (1..=6).contains(&level) && (rest.is_empty() || rest.starts_with(' ')) {
+                            // A closing run of #s is not part of the text
+                            let text = rest.trim().trim_end_matches('#').trim_end().to_string();
+                            headings.push(Heading { level, text, start: line_start });
+                        }
+                    }
+                }
+            }
+        }
+        line_start = line_end + 1;
+    }
+
+    headings
+}
+
+/// Scan `source.text[body_start..end]` for choice links. A `{once}`,
+/// `{sticky}` or `{if: ...}` prefix that is not directly followed by a
+/// well-formed link is reported as a syntax error.
+pub fn scan_choices(source: &Source, body_start: usize, end: usize) -> (Vec<ChoiceMarkup>, Vec<PackardError>) {
+    let (choices, _, errors) = scan(source, body_start, end);
+    (choices, errors)
+}
+
+/// The parts of `source.text[body_start..end]` that are shown as written
+/// rather than read for markup: fenced code blocks, callouts, comments and
+/// inline code. [`scan_choices`] finds no links in them, and templates
+/// leave them alone.
+pub fn scan_verbatim(source: &Source, body_start: usize, end: usize) -> Vec<Span> {
+    scan(source, body_start, end).1.into_iter().map(|(from, to)| source.span(from, to)).collect()
+}
+
+/// Choice links, the ranges skipped as verbatim and malformed choice
+/// prefixes, in order.
+fn scan(source: &Source, body_start: usize, end: usize) -> (Vec<ChoiceMarkup>, Vec<(usize, usize)>, Vec<PackardError>) {
+    let text = &source.text[..end];
+    let mut choices = Vec::new();
+    let mut verbatim = Vec::new();
+    let mut errors = Vec::new();
+    let mut fence: Option<(char, usize)> = None;
+    let mut comment: Option<(Comment, usize)> = None;
+    let mut in_callout = false;
+
+    let mut line_start = body_start;
+    while line_start < text.len() {
+        let line_end = text[line_start..].find('\n').map(|i| line_start + i).unwrap_or(text.len());
+        let line = &text[line_start..line_end];
+        let next_line = (line_end + 1).min(text.len());
+
+        if comment.is_none() {
+            let indent = line.len() - line.trim_start_matches(' ').len();
+            let trimmed = &line[indent..];
+
+            if let Some((ch, len)) = fence {
+                if indent < 4 && fence_run(trimmed, ch) >= len && trimmed.trim_start_matches(ch).trim().is_empty() {
+                    fence = None;
+                }
+                verbatim.push((line_start, line_end));
+                line_start = next_line;
+                continue;
+            }
+
+            if indent < 4 {
+                if let Some(ch) = ['`', '~'].into_iter().find(|c| trimmed.starts_with(*c)) {
+                    let len = fence_run(trimmed, ch);
+                    if len >= 3 {
+                        fence = Some((ch, len));
+                        verbatim.push((line_start, line_end));
+                        line_start = next_line;
+                        continue;
+                    }
+                }
+            }
+
+            if let Some(quoted) = trimmed.strip_prefix('>') {
+                if in_callout || quoted.trim_start().starts_with("[!") {
+                    in_callout = true;
+                    verbatim.push((line_start, line_end));
+                    line_start = next_line;
+                    continue;
+                }
+            } else {
+                in_callout = false;
+            }
+        }
+
+        scan_line(source, line_start, line_end, &mut comment, &mut choices, &mut verbatim, &mut errors);
+        line_start = next_line;
+    }
+    if let Some((_, start)) = comment {
+        verbatim.push((start, text.len()));
+    }
+
+    (choices, verbatim, errors)
+}
+
+fn fence_run(s: &str, ch: char) -> usize {
+    s.chars().take_while(|c| *c == ch).count()
+}
+
+fn scan_line(
+    source: &Source,
+    start: usize,
+    end: usize,
+    comment: &mut Option<(Comment, usize)>,
+    choices: &mut Vec<ChoiceMarkup>,
+    verbatim: &mut Vec<(usize, usize)>,
+    errors: &mut Vec<PackardError>,
+) {
+    let text = source.text;
+    let mut i = start;
+
+    while i < end {
+        let rest = &text[i..end];
+
+        if let Some((kind, opened)) = *comment {
+            match rest.find(kind.terminator()) {
+                Some(pos) => {
+                    i += pos + kind.terminator().len();
+                    verbatim.push((opened, i));
+                    *comment = None;
+                    continue;
+                }
+                None => return,
+            }
+        }
+
+        if rest.starts_with("<!--") {
+            *comment = Some((Comment::Html, i));
+            i += 4;
+        } else if rest.starts_with("%%") {
+            *comment = Some((Comment::Obsidian, i));
+            i += 2;
+        } else if rest.starts_with('`') {
+            let run = fence_run(rest, '`');
+            let delimiter = &rest[..run];
+            let len = match rest[run..].find(delimiter) {
+                Some(pos) => run + pos + run,
+                None => run,
+            };
+            verbatim.push((i, i + len));
+            i += len;
+        } else if ["{if:", "{once}", "{sticky}"].iter().any(|p| rest.starts_with(p)) {
+            i = match scan_prefixed_choice(source, i, end) {
+                Ok((choice, next)) => {
+                    choices.push(choice);
+                    next
+                }
+                Err(error) => {
+                    errors.extend(error);
+                    i + 4
+                }
+            };
+        } else if rest.starts_with("![[") {
+            i += match rest.find("]]") {
+                Some(pos) => pos + 2,
+                None => 3,
+            };
+        } else if rest.starts_with("[[") {
+            i = match scan_link(source, i, end, None) {
+                Some((choice, next)) => {
+                    choices.push(choice);
+                    next
+                }
+                None => i + 2,
+            };
+        } else {
+            i += rest.chars().next().map(char::len_utf8).unwrap_or(1);
+        }
+    }
+}
+
+/// `{if: condition}`, `{once}` and `{sticky}` prefixes, in any order,
+/// immediately followed by a link.
+fn scan_prefixed_choice(
+    source: &Source,
+    start: usize,
+    end: usize,
+) -> std::result::Result<(ChoiceMarkup, usize), Option<PackardError>> {
+    let text = source.text;
+    let mut condition = None;
+    let mut once = None;
+    let mut pos = start;
+    let malformed = |pos: usize| {
+        PackardError::syntax("a choice prefix must be followed directly by a [[target|label]] link")
+            .with_span(&source.span(start, pos))
+    };
+
+    loop {
+        let rest = &text[pos..end];
+        if rest.starts_with("{once}") {
+            once = Some(true);
+            pos += "{once}".len();
+        } else if rest.starts_with("{sticky}") {
+            once = Some(false);
+            pos += "{sticky}".len();
+        } else if rest.starts_with("{if:") && condition.is_none() {
+            let inner_start = pos + "{if:".len();
+            let close = find_closing(text, inner_start, end, '{', '}').ok_or(None)?;
+
+            let raw = &text[inner_start..close];
+            let leading = raw.len() - raw.trim_start().len();
+            condition = Some(Fragment {
+                text: raw.trim().to_string(),
+                span: source.span(inner_start + leading, inner_start + leading + raw.trim().len()),
+            });
+            pos = close + 1;
+        } else if rest.starts_with("[[") {
+            break;
+        } else if once.is_some() || rest.trim_start().starts_with("[[") {
+            return Err(Some(malformed(pos)));
+        } else {
+            // `{if: ...}` that starts a conditional block of prose
+            return Err(None);
+        }
+    }
+
+    let (mut choice, next) = scan_link(source, pos, end, condition).ok_or_else(|| Some(malformed(pos)))?;
+    choice.once = once;
+    choice.span = source.span(start, next);
+    Ok((choice, next))
+}
+
+/// `[[target|label]]`, `[[target]]`, `[[target#Heading|label]]` or, within
+/// the same note, `[[#Heading|label]]`, with an optional `(effects)` suffix.
+fn scan_link(
+    source: &Source,
+    start: usize,
+    end: usize,
+    condition: Option<Fragment>,
+) -> Option<(ChoiceMarkup, usize)> {
+    let text = source.text;
+    let inner_start = start + 2;
+    let close = inner_start + text[inner_start..end].find("]]")?;
+    let (link, label) = text[inner_start..close].split_once('|').unwrap_or((&text[inner_start..close], ""));
+    let label_start = close - label.len() + (label.len() - label.trim_start().len());
+
+    let (target, heading) = match link.split_once('#') {
+        Some((target, heading)) => (target.trim(), Some(heading.trim())),
+        None => (link.trim(), None),
+    };
+    let label = label.trim();
+    // `#^block` links point into a note's text, not at a scene
+    if (target.is_empty() && heading.is_none()) || heading.is_some_and(|h| h.is_empty() || h.starts_with('^')) {
+        return None;
+    }
+
+    let mut next = close + 2;
+    let mut effects = None;
+    if text[next..end].starts_with('(') {
+        if let Some(paren) = find_closing(text, next + 1, end, '(', ')') {
+            let raw = &text[next + 1..paren];
+            let leading = raw.len() - raw.trim_start().len();
+            let effects_start = next + 1 + leading;
+            effects = Some(Fragment {
+                text: raw.trim().to_string(),
+                span: source.span(effects_start, effects_start + raw.trim().len()),
+            });
+            next = paren + 1;
+        }
+    }
+
+    let mut block_id = None;
+    let after = &text[next..end];
+    if let Some(rest) = after.trim_start().strip_prefix('^') {
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+            .unwrap_or(rest.len());
+        if len > 0 {
+            block_id = Some(rest[..len].to_string());
+            next = end - rest.len() + len;
+        }
+    }
+
+    Some((
+        ChoiceMarkup {
+            target: target.to_string(),
+            heading: heading.map(str::to_string),
+            label: label.to_string(),
+            label_span: source.span(label_start, label_start + label.len()),
+            condition,
+            effects,
+            block_id,
+            once: None,
+            span: source.span(start, next),
+        },
+        next,
+    ))
+}
+
+/// Lower-case `text`, keeping letters and digits and joining words with `-`,
+/// e.g. "Take the key!" becomes "take-the-key".
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for word in text.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()) {
+        if !slug.is_empty() {
+            slug.push('-');
+        }
+        slug.extend(word.chars().flat_map(char::to_lowercase));
+    }
+    slug
+}
+
+/// Find the byte offset of the delimiter closing an already opened group,
+/// honouring nested groups and quoted strings.
+pub(crate) fn find_closing(text: &str, start: usize, end: usize, open: char, close: char) -> Option<usize> {
+    let mut depth = 0;
+    let mut quote: Option<char> = None;
+
+    for (offset, ch) in text[start..end].char_indices() {
+        match quote {
+            Some(q) if ch == q => quote = None,
+            Some(_) => {}
+            None if ch == '"' || ch == '\'' => quote = Some(ch),
+            None if ch == open => depth += 1,
+            None if ch == close && depth == 0 => return Some(start + offset),
+            None if ch == close => depth -= 1,
+            None => {}
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan(text: &str) -> Vec<ChoiceMarkup> {
+        let source = Source::new("test.md", text);
+        let (choices, errors) = scan_choices(&source, 0, text.len());
+        assert!(errors.is_empty(), "unexpected errors: {:?}", errors);
+        choices
+    }
+
+    #[test]
+    fn test_conditional_choice_yielded_once() {
+        let choices = scan("[[a|First]]\n{if: x > 1}[[b|Second]](y = 2)\n[[c|Third]]");
+        let targets: Vec<_> = choices.iter().map(|c| c.target.as_str()).collect();
+        assert_eq!(targets, vec!["a", "b", "c"]);
+        assert_eq!(choices[1].condition.as_ref().unwrap().text, "x > 1");
+        assert_eq!(choices[1].effects.as_ref().unwrap().text, "y = 2");
+    }
+
+    #[test]
+    fn test_spans() {
+        let choices = scan("Intro\n\n  {if: x > 1}[[b|Go]](y += 1)");
+        let choice = &choices[0];
+        assert_eq!((choice.span.line, choice.span.column), (3, 3));
+
+        let condition = choice.condition.as_ref().unwrap();
+        assert_eq!((condition.span.line, condition.span.column), (3, 8));
+
+        let effects = choice.effects.as_ref().unwrap();
+        assert_eq!((effects.span.line, effects.span.column), (3, 23));
+        assert_eq!(effects.span.file, "test.md");
+    }
+
+    #[test]
+    fn test_skips_code_callouts_and_comments() {
+        let text = r#"```
+[[code|In a fence]]
+```
+Some `[[inline|code]]` here.
+> [!note]
+> [[callout|In a callout]]
+<!-- [[html|comment]]
+still commented [[more|links]] -->
+%% [[obsidian|comment]] %%
+![[embedded|image]]
+[[real|Real choice]]"#;
+
+        let choices = scan(text);
+        assert_eq!(choices.len(), 1);
+        assert_eq!(choices[0].target, "real");
+        assert_eq!(choices[0].span.line, 11);
+    }
+
+    #[test]
+    fn test_effects_with_nested_parentheses() {
+        let choices = scan("[[a|Go]](hp = (1 + 2); name = \"a)b\")");
+        assert_eq!(choices[0].effects.as_ref().unwrap().text, "hp = (1 + 2); name = \"a)b\"");
+    }
+
+    #[test]
+    fn test_once_and_sticky_prefixes() {
+        let choices = scan("{once}[[a|A]]\n{sticky}{if: x > 1}[[b|B]]\n{if: x > 1}{once}[[c|C]]\n[[d|D]]\n{if: x}Prose.{endif}");
+        let flags: Vec<_> = choices.iter().map(|c| (c.target.as_str(), c.once)).collect();
+        assert_eq!(flags, vec![("a", Some(true)), ("b", Some(false)), ("c", Some(true)), ("d", None)]);
+        assert_eq!(choices[1].condition.as_ref().unwrap().text, "x > 1");
+        assert_eq!(choices[2].span.column, 1);
+    }
+
+    #[test]
+    fn test_malformed_prefixes_are_errors() {
+        let text = "{once} [[e|E]]\n{once}[[f|F]\n{if: x > 1} [[g|G]]\n{sticky}Text";
+        let (choices, errors) = scan_choices(&Source::new("test.md", text), 0, text.len());
+        assert_eq!(choices.iter().map(|c| c.target.as_str()).collect::<Vec<_>>(), vec!["e", "g"]);
+        assert!(choices.iter().all(|c| c.once.is_none() && c.condition.is_none()));
+
+        let spans: Vec<_> = errors.iter().map(|e| e.span().map(|s| (s.line, s.column, s.end - s.start))).collect();
+        assert_eq!(spans, vec![Some((1, 1, 6)), Some((2, 1, 6)), Some((3, 1, 11)), Some((4, 1, 8))]);
+        assert!(errors.iter().all(|e| matches!(e, PackardError::Syntax { .. })));
+    }
+
+    #[test]
+    fn test_block_ids() {
+        let choices = scan("[[a|Go]](x = 1) ^go-left\n[[b|Stay]]\n[[c|Wait]] ^");
+        assert_eq!(choices[0].block_id.as_deref(), Some("go-left"));
+        assert_eq!(choices[0].span.end, "[[a|Go]](x = 1) ^go-left".len());
+        assert_eq!(choices[1].block_id, None);
+        assert_eq!(choices[2].block_id, None);
+        assert_eq!(slugify("Take the key!"), "take-the-key");
+    }
+
+    #[test]
+    fn test_bare_and_heading_links() {
+        let choices = scan("[[journal]] [[start#Morning|Wake]] [[start#^block]] [[a#]] [[ | x]] [[#Local]]");
+        assert_eq!(choices.len(), 3);
+        assert_eq!((choices[2].target.as_str(), choices[2].heading.as_deref()), ("", Some("Local")));
+        assert_eq!((choices[0].target.as_str(), choices[0].label.as_str()), ("journal", ""));
+        assert_eq!(choices[0].heading, None);
+        assert_eq!(choices[1].target, "start");
+        assert_eq!(choices[1].heading.as_deref(), Some("Morning"));
+        assert_eq!(choices[1].label, "Wake");
+    }
+
+    #[test]
+    fn test_headings() {
+        let text = "# Title\nText #not\n```\n## In code\n```\n##Nope\n### Deeper ##\n";
+        let headings = scan_headings(&Source::new("test.md", text), 0, text.len());
+        let found: Vec<_> = headings.iter().map(|h| (h.level, h.text.as_str())).collect();
+        assert_eq!(found, vec![(1, "Title"), (3, "Deeper")]);
+        assert_eq!(headings[1].start, text.find("###").unwrap());
+    }
+
+    #[test]
+    fn test_split_frontmatter() {
+        let content = "---\ntitle: A\n---\nBody --- text";
+        let (frontmatter, body, offset) = split_frontmatter(content);
+        assert_eq!(frontmatter, Some("title: A\n"));
+        assert_eq!(body, "Body --- text");
+        assert_eq!(&content[offset..], body);
+
+        let (frontmatter, body, offset) = split_frontmatter("No frontmatter\n---\n");
+        assert_eq!(frontmatter, None);
+        assert_eq!(body, "No frontmatter\n---\n");
+        assert_eq!(offset, 0);
+    }
+}
@@ -0,0 +1,115 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+
+/// Small deterministic PRNG (SplitMix64). It lives in `State`, so undo
+/// snapshots and saves capture it along with the variables.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rng {
+    seed: u64,
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { seed, state: seed }
+    }
+
+    /// Seed from the clock, for sessions that do not ask for a fixed seed.
+    pub fn from_time() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Rng::new(nanos)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        mix(self.state)
+    }
+
+    /// A stream of its own for one place that draws numbers, e.g. a choice's
+    /// condition, named by `site`. It starts from the current position
+    /// without moving it, so every site rolls independently of the others
+    /// and a replay from the same seed rolls the same.
+    pub fn fork(&self, site: &str) -> Rng {
+        Rng { seed: self.seed, state: mix(self.state ^ stable_hash(site)) }
+    }
+
+    /// A number between `min` and `max`, both inclusive.
+    pub fn range(&mut self, min: i64, max: i64) -> i64 {
+        let (min, max) = if min <= max { (min, max) } else { (max, min) };
+        let span = (max as i128 - min as i128 + 1) as u128;
+        (min as i128 + (self.next_u64() as u128 % span) as i128) as i64
+    }
+
+    /// True `percent` times out of a hundred.
+    pub fn chance(&mut self, percent: f64) -> bool {
+        // Top 53 bits give a uniform float in [0, 1)
+        let unit = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        unit * 100.0 < percent
+    }
+}
+
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// FNV-1a, so derived streams do not depend on the standard library's hasher.
+pub(crate) fn stable_hash(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |hash, b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Rng::from_time()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_sequence() {
+        let mut a = Rng::new(7);
+        let mut b = Rng::new(7);
+        for _ in 0..10 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_eq!(a.seed(), 7);
+    }
+
+    #[test]
+    fn test_range_is_inclusive() {
+        let mut rng = Rng::new(1);
+        let rolls: Vec<_> = (0..200).map(|_| rng.range(1, 6)).collect();
+        assert!(rolls.iter().all(|r| (1..=6).contains(r)));
+        assert!(rolls.contains(&1) && rolls.contains(&6));
+        assert_eq!(rng.range(3, 3), 3);
+    }
+
+    #[test]
+    fn test_forks_are_independent() {
+        let rng = Rng::new(5);
+        let (mut a, mut b) = (rng.fork("start#^a"), rng.fork("start#^b"));
+        let same = (0..100).filter(|_| a.range(1, 6) == b.range(1, 6)).count();
+        assert!(same < 40, "{}", same);
+        assert_eq!(rng.fork("start#^a").next_u64(), rng.fork("start#^a").next_u64());
+        assert_eq!(rng, Rng::new(5));
+    }
+
+    #[test]
+    fn test_chance() {
+        let mut rng = Rng::new(3);
+        let hits = (0..1000).filter(|_| rng.chance(30.0)).count();
+        assert!((250..350).contains(&hits), "{}", hits);
+        assert!((0..100).all(|_| !rng.chance(0.0) && rng.chance(100.0)));
+    }
+}
@@ -1,23 +1,96 @@
 use crate::vault::Vault;
-use crate::scene::Scene;
-use crate::effects::State;
+use crate::scene::{Choice, Scene};
+use crate::effects::{Change, Effect, State, UndefinedPolicy};
+use crate::parser::Span;
+use crate::check::Diagnostic;
+use crate::error::{PackardError, Result};
+use crate::save::{self, SaveFile, SAVE_VERSION};
+use crate::template::{self, Template};
+use crate::dialogue::{self, DialogueLine};
+use crate::rng::Rng;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::path::Path;
+
+/// How many choices `Runtime` remembers for undo unless told otherwise.
+pub const DEFAULT_HISTORY_LIMIT: usize = 100;
+
+/// A choice the player made, with everything needed to step back before it.
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    /// Scene the choice was made in
+    pub scene_id: String,
+    heading: Option<String>,
+    /// State before the choice's effects were applied
+    pub state: State,
+    pub choice_index: usize,
+    pub choice_id: String,
+    pub choice_label: String,
+    visit_count: usize,
+    choice_count: usize,
+}
+
+/// What taking a choice does: where it leads and which variables it changes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChoiceOutcome {
+    pub from: String,
+    pub to: String,
+    pub changes: Vec<Change>,
+}
 
 pub struct Runtime {
     vault: Vault,
     current_scene_id: String,
+    /// Heading the current scene was entered at by a `[[scene#Heading]]` link
+    current_heading: Option<String>,
     state: State,
+    /// Every scene visited, in order, starting with the start scene
+    visit_history: Vec<String>,
+    /// Id of every choice taken, in order; replaying them reproduces the session
+    choice_log: Vec<String>,
+    /// Choices taken, oldest first, bounded by `history_limit`
+    history: VecDeque<HistoryEntry>,
+    history_limit: usize,
+    /// Problems met while playing (e.g. a condition reading an undefined
+    /// variable in strict mode), waiting for the front-end to collect them
+    diagnostics: RefCell<Vec<Diagnostic>>,
 }
 
 impl Runtime {
-    pub fn new(vault: Vault, start_scene: &str) -> Result<Self, String> {
+    pub fn new(vault: Vault, start_scene: &str) -> Result<Self> {
+        Self::with_rng(vault, start_scene, Rng::from_time())
+    }
+
+    /// Start a session whose random numbers are fixed by `seed`, so the same
+    /// choices replay exactly.
+    pub fn with_seed(vault: Vault, start_scene: &str, seed: u64) -> Result<Self> {
+        Self::with_rng(vault, start_scene, Rng::new(seed))
+    }
+
+    #[allow(clippy::nonminimal_bool)]
+    fn with_rng(vault: Vault, start_scene: &str, rng: Rng) -> Result<Self> {
         if !vault.get_scene(start_scene).is_some() {
-            return Err(format!("Start scene '{}' not found", start_scene));
+            return Err(PackardError::SceneNotFound { id: start_scene.to_string(), span: None });
         }
 
+        let mut state = State::new();
+        state.undefined = vault.manifest.undefined_variables;
+        state.rng = rng;
+        state.variables.extend(vault.manifest.variables.clone());
+        state.record_visit(start_scene);
+        let start = vault.note_of(vault.get_scene(start_scene).unwrap());
+        apply_hook(&mut state, &start.on_enter, start.on_enter_span.as_ref(), &format!("{}:on_enter", start.id))?;
+
         Ok(Runtime {
             vault,
             current_scene_id: start_scene.to_string(),
-            state: State::new(),
+            current_heading: None,
+            state,
+            visit_history: vec![start_scene.to_string()],
+            choice_log: Vec::new(),
+            history: VecDeque::new(),
+            history_limit: DEFAULT_HISTORY_LIMIT,
+            diagnostics: RefCell::new(Vec::new()),
         })
     }
 
@@ -32,8 +105,17 @@ impl Runtime {
             .iter()
             .enumerate()
             .filter(|(_, choice)| {
-                if let Some(condition) = &choice.condition {
-                    condition.evaluate(&self.state).unwrap_or(false)
+                if choice.once && self.state.visits(&choice.visit_key(&scene.id)) > 0 {
+                    false
+                } else if let Some(condition) = &choice.condition {
+                    condition.evaluate_at(&self.state, &choice.visit_key(&scene.id)).unwrap_or_else(|e| {
+                        let e = match &choice.condition_span {
+                            Some(span) => e.with_span(span),
+                            None => e,
+                        };
+                        self.report(Diagnostic::from(e));
+                        false
+                    })
                 } else {
                     true
                 }
@@ -41,6 +123,88 @@ impl Runtime {
             .collect()
     }
 
+    /// The current scene's prose rendered for the current state. Conditions
+    /// that fail to evaluate count as false and are reported as diagnostics,
+    /// as are placeholders with no value.
+    pub fn render_scene(&self) -> String {
+        self.render(self.current_prose())
+    }
+
+    /// The dialogue lines of the current scene's rendered prose, as
+    /// `**Name**: "text"` with placeholders in either part filled in.
+    pub fn render_dialogue(&self) -> Vec<DialogueLine> {
+        let (text, values) = self.render_marked(self.current_prose());
+        dialogue::extract_dialogue(&text)
+            .into_iter()
+            .map(|line| DialogueLine {
+                character: template::fill(&line.character, &values),
+                text: template::fill(&line.text, &values),
+            })
+            .collect()
+    }
+
+    /// The current scene's rendered prose without its dialogue lines, for
+    /// front ends that show [`Runtime::render_dialogue`] apart.
+    pub fn render_narration(&self) -> String {
+        let (text, values) = self.render_marked(self.current_prose());
+        template::fill(&dialogue::strip_dialogue(&text), &values)
+    }
+
+    /// The section the scene was entered at, or else all of its prose.
+    fn current_prose(&self) -> &Template {
+        let scene = self.current_scene();
+        match self.current_heading.as_deref().and_then(|h| scene.section(h)) {
+            Some(section) => &section.prose,
+            None => &scene.prose,
+        }
+    }
+
+    /// Heading the current scene was entered at, if the link named one.
+    pub fn current_heading(&self) -> Option<&str> {
+        self.current_heading.as_deref()
+    }
+
+    /// A choice's label with its placeholders filled in.
+    pub fn choice_label(&self, choice: &Choice) -> String {
+        self.render(&choice.label_template)
+    }
+
+    fn render(&self, template: &Template) -> String {
+        let mut errors = Vec::new();
+        let text = template.render(&self.state, &self.vault, &self.current_scene_id, &mut errors);
+        for error in errors {
+            self.report(Diagnostic::from(error));
+        }
+        text
+    }
+
+    /// Render with placeholders left as markers; dialogue is found in the
+    /// text before the values go in, so no value can start or end a line.
+    fn render_marked(&self, template: &Template) -> (String, Vec<String>) {
+        let mut errors = Vec::new();
+        let marked = template.render_marked(&self.state, &self.vault, &self.current_scene_id, &mut errors);
+        for error in errors {
+            self.report(Diagnostic::from(error));
+        }
+        marked
+    }
+
+    /// Take the problems reported since the last call. A choice whose
+    /// condition fails to evaluate is hidden and reported here.
+    pub fn take_diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics.take()
+    }
+
+    fn report(&self, diagnostic: Diagnostic) {
+        let mut diagnostics = self.diagnostics.borrow_mut();
+        let duplicate = diagnostics
+            .iter()
+            .any(|d| d.message == diagnostic.message && d.span == diagnostic.span);
+        if !duplicate {
+            diagnostics.push(diagnostic);
+        }
+    }
+
     pub fn current_scene_id(&self) -> &str {
         &self.current_scene_id
     }
@@ -49,24 +213,707 @@ impl Runtime {
         &self.state
     }
 
-    pub fn choose(&mut self, choice_index: usize) -> Result<(), String> {
+    /// Seed of the session's random numbers; pass it to
+    /// [`Runtime::with_seed`] to replay the session.
+    pub fn seed(&self) -> u64 {
+        self.state.rng.seed()
+    }
+
+    pub fn visit_history(&self) -> &[String] {
+        &self.visit_history
+    }
+
+    /// Ids of the choices taken so far, in order.
+    pub fn choice_log(&self) -> &[String] {
+        &self.choice_log
+    }
+
+    /// Choices taken so far that can still be undone, oldest first.
+    pub fn history(&self) -> &VecDeque<HistoryEntry> {
+        &self.history
+    }
+
+    /// Limit how many choices are kept for undo; older ones are dropped.
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history_limit = limit;
+        while self.history.len() > limit {
+            self.history.pop_front();
+        }
+    }
+
+    /// Override the vault's `undefined_variables` setting for this session,
+    /// including the states kept for undo.
+    pub fn set_undefined_policy(&mut self, policy: UndefinedPolicy) {
+        self.state.undefined = policy;
+        for entry in &mut self.history {
+            entry.state.undefined = policy;
+        }
+    }
+
+    /// Step back before the most recent choice.
+    pub fn undo(&mut self) -> Result<()> {
+        match self.history.len() {
+            0 => Err(PackardError::runtime("Nothing to undo")),
+            len => self.rewind_to(len - 1),
+        }
+    }
+
+    /// Return to the moment before `history()[n]` was chosen, forgetting it
+    /// and every later choice.
+    pub fn rewind_to(&mut self, n: usize) -> Result<()> {
+        if n >= self.history.len() {
+            return Err(PackardError::runtime(format!(
+                "Cannot rewind to step {}: only {} step(s) in history",
+                n,
+                self.history.len()
+            )));
+        }
+
+        let entry = self.history.drain(n..).next().unwrap();
+        self.current_scene_id = entry.scene_id;
+        self.current_heading = entry.heading;
+        self.state = entry.state;
+        self.visit_history.truncate(entry.visit_count);
+        self.choice_log.truncate(entry.choice_count);
+        Ok(())
+    }
+
+    /// Work out what taking a choice would do without taking it, e.g. to
+    /// show "this will cost 10 gold" before the player commits.
+    pub fn preview_choice(&self, choice_index: usize) -> Result<ChoiceOutcome> {
+        self.resolve(choice_index).map(|(outcome, _)| outcome)
+    }
+
+    /// Take a choice, applying its effects and moving to its target. Effects
+    /// run as one all-or-nothing batch: the choice's own, then the current
+    /// scene's `on_exit`, then the target's `on_enter`. Fails
+    /// with `ChoiceUnavailable` if the choice's condition does not hold. On
+    /// error nothing changes.
+    pub fn choose(&mut self, choice_index: usize) -> Result<ChoiceOutcome> {
+        let (outcome, state) = self.resolve(choice_index)?;
+        let choice = &self.current_scene().choices[choice_index];
+        let (choice_id, choice_label) = (choice.id.clone(), choice.label.clone());
+        let heading = choice.heading.clone();
+
+        let entry = HistoryEntry {
+            scene_id: self.current_scene_id.clone(),
+            heading: std::mem::replace(&mut self.current_heading, heading),
+            state: std::mem::replace(&mut self.state, state),
+            choice_index,
+            choice_id,
+            choice_label,
+            visit_count: self.visit_history.len(),
+            choice_count: self.choice_log.len(),
+        };
+
+        self.choice_log.push(entry.choice_id.clone());
+        self.visit_history.push(outcome.to.clone());
+        self.current_scene_id = outcome.to.clone();
+
+        self.history.push_back(entry);
+        if self.history.len() > self.history_limit {
+            self.history.pop_front();
+        }
+        Ok(outcome)
+    }
+
+    /// Take the current scene's choice with the given id.
+    pub fn choose_by_id(&mut self, id: &str) -> Result<ChoiceOutcome> {
+        let index = self.choice_index(id)?;
+        self.choose(index)
+    }
+
+    /// Like [`Runtime::preview_choice`], addressing the choice by id.
+    pub fn preview_choice_by_id(&self, id: &str) -> Result<ChoiceOutcome> {
+        self.preview_choice(self.choice_index(id)?)
+    }
+
+    /// Take a recorded sequence of choice ids, stopping at the first that fails.
+    pub fn replay(&mut self, ids: &[String]) -> Result<()> {
+        for id in ids {
+            self.choose_by_id(id)?;
+        }
+        Ok(())
+    }
+
+    fn choice_index(&self, id: &str) -> Result<usize> {
+        self.current_scene()
+            .choices
+            .iter()
+            .position(|c| c.id == id)
+            .ok_or_else(|| PackardError::ChoiceNotFound { id: id.to_string() })
+    }
+
+    /// The outcome of a choice together with the state it would leave behind.
+    fn resolve(&self, choice_index: usize) -> Result<(ChoiceOutcome, State)> {
         let scene = self.current_scene();
-        
-        if choice_index >= scene.choices.len() {
-            return Err(format!("Invalid choice: {}", choice_index));
+
+        let choice = scene
+            .choices
+            .get(choice_index)
+            .ok_or(PackardError::InvalidChoice { index: choice_index })?;
+
+        let site = choice.visit_key(&self.current_scene_id);
+        if choice.once && self.state.visits(&site) > 0 {
+            return Err(PackardError::ChoiceUnavailable { id: choice.id.clone(), span: Some(choice.span.clone()) });
         }
 
-        let choice = scene.choices[choice_index].clone();
-        let next_id = choice.target.clone();
-        
-        if !self.vault.get_scene(&next_id).is_some() {
-            return Err(format!("Scene '{}' not found", next_id));
+        if let Some(condition) = &choice.condition {
+            let available = condition.evaluate_at(&self.state, &site).map_err(|e| match &choice.condition_span {
+                Some(span) => e.with_span(span),
+                None => e,
+            })?;
+            if !available {
+                return Err(PackardError::ChoiceUnavailable {
+                    id: choice.id.clone(),
+                    span: choice.condition_span.clone(),
+                });
+            }
         }
 
-        // Apply effects before changing scene
-        self.state.apply_effects(&choice.effects)?;
+        let target = self
+            .vault
+            .get_scene(&choice.target)
+            .ok_or_else(|| PackardError::SceneNotFound { id: choice.target.clone(), span: Some(choice.span.clone()) })?;
+
+        // Hooks belong to the whole note, so moving between its parts runs none
+        let (from, to) = (self.vault.note_of(scene), self.vault.note_of(target));
+        let hooks = from.id != to.id || scene.id == target.id;
+        let on_exit: &[Effect] = if hooks { &from.on_exit } else { &[] };
+        let on_enter: &[Effect] = if hooks { &to.on_enter } else { &[] };
+
+        let mut state = self.state.clone();
+        apply_hook(&mut state, &choice.effects, choice.effects_span.as_ref(), &format!("{}:effects", site))?;
+        apply_hook(&mut state, on_exit, from.on_exit_span.as_ref(), &format!("{}:on_exit", from.id))?;
+        state.record_visit(&site);
+        state.record_visit(&choice.target);
+        state.turns += 1;
+        // One draw per turn; every condition and batch of effects forks its
+        // own stream from the position it leaves, see `Rng::fork`
+        state.rng.next_u64();
+        apply_hook(&mut state, on_enter, to.on_enter_span.as_ref(), &format!("{}:on_enter", to.id))?;
+
+        let effects = choice.effects.iter().chain(on_exit).chain(on_enter);
+        let changes = state.changes_since(&self.state, effects);
+
+        let outcome = ChoiceOutcome {
+            from: self.current_scene_id.clone(),
+            to: choice.target.clone(),
+            changes,
+        };
+        Ok((outcome, state))
+    }
+
+    /// Capture the session as a save file.
+    pub fn snapshot(&self) -> SaveFile {
+        SaveFile {
+            version: SAVE_VERSION,
+            vault: save::fingerprint(&self.vault),
+            scene: self.current_scene_id.clone(),
+            heading: self.current_heading.clone(),
+            variables: self.state.variables.clone().into_iter().collect(),
+            history: self.visit_history.clone(),
+            choices: self.choice_log.clone(),
+            visits: self.state.visits.clone().into_iter().collect(),
+            turns: self.state.turns,
+            rng: Some(self.state.rng.clone()),
+        }
+    }
+
+    /// Replace the session with one captured by [`Runtime::snapshot`].
+    /// Fails, leaving the session untouched, if the vault has changed since.
+    pub fn restore_snapshot(&mut self, save: SaveFile) -> Result<()> {
+        if save.vault != save::fingerprint(&self.vault) {
+            return Err(PackardError::InvalidSave {
+                path: None,
+                message: "the vault has changed since this game was saved".to_string(),
+            });
+        }
+        if self.vault.get_scene(&save.scene).is_none() {
+            return Err(PackardError::InvalidSave {
+                path: None,
+                message: format!("scene '{}' no longer exists", save.scene),
+            });
+        }
 
-        self.current_scene_id = next_id;
+        self.current_scene_id = save.scene;
+        self.current_heading = save.heading;
+        self.state.variables = save.variables.into_iter().collect();
+        self.state.visits = save.visits.into_iter().collect();
+        self.state.turns = save.turns;
+        if let Some(rng) = save.rng {
+            self.state.rng = rng;
+        }
+        self.visit_history = save.history;
+        self.choice_log = save.choices;
+        self.history.clear();
         Ok(())
     }
+
+    /// Write the session to a YAML save file.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.snapshot().write(path.as_ref())
+    }
+
+    /// Load a session written by [`Runtime::save`].
+    pub fn restore(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let save = SaveFile::read(path)?;
+        self.restore_snapshot(save).map_err(|e| match e {
+            PackardError::InvalidSave { path: None, message } => PackardError::InvalidSave {
+                path: Some(path.to_path_buf()),
+                message,
+            },
+            e => e,
+        })
+    }
+}
+
+/// Apply a batch of effects with random numbers forked for `site`, pointing
+/// any error at the markup they came from.
+fn apply_hook(state: &mut State, effects: &[Effect], span: Option<&Span>, site: &str) -> Result<()> {
+    state.apply_effects_at(effects, site).map(|_| ()).map_err(|e| match span {
+        Some(span) => e.with_span(span),
+        None => e,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::effects::UndefinedPolicy;
+    use crate::vault::tests::vault;
+
+    fn story() -> Vault {
+        vault(&[
+            ("start", "[[middle|Go]](gold += 5)"),
+            ("middle", "[[end|Finish]](gold += 1)\n[[start|Back]]"),
+            ("end", "Done."),
+        ])
+    }
+
+    fn gold(runtime: &Runtime) -> Option<i64> {
+        runtime.state().get("gold").and_then(|v| v.as_i64())
+    }
+
+    #[test]
+    fn test_undo_restores_scene_and_state() {
+        let mut runtime = Runtime::new(story(), "start").unwrap();
+        runtime.choose(0).unwrap();
+        runtime.choose(0).unwrap();
+        assert_eq!(gold(&runtime), Some(6));
+
+        runtime.undo().unwrap();
+        assert_eq!(runtime.current_scene_id(), "middle");
+        assert_eq!(gold(&runtime), Some(5));
+        assert_eq!(runtime.visit_history(), &["start".to_string(), "middle".to_string()]);
+
+        runtime.undo().unwrap();
+        assert_eq!(runtime.current_scene_id(), "start");
+        assert_eq!(gold(&runtime), None);
+        assert!(runtime.undo().is_err());
+    }
+
+    #[test]
+    fn test_rewind_to_drops_later_choices() {
+        let mut runtime = Runtime::new(story(), "start").unwrap();
+        runtime.choose(0).unwrap();
+        runtime.choose(1).unwrap();
+        runtime.choose(0).unwrap();
+
+        let labels: Vec<_> = runtime.history().iter().map(|h| h.choice_label.as_str()).collect();
+        assert_eq!(labels, vec!["Go", "Back", "Go"]);
+
+        runtime.rewind_to(1).unwrap();
+        assert_eq!(runtime.current_scene_id(), "middle");
+        assert_eq!(runtime.history().len(), 1);
+        assert!(runtime.rewind_to(1).is_err());
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let mut runtime = Runtime::new(story(), "start").unwrap();
+        runtime.set_history_limit(2);
+        for _ in 0..3 {
+            runtime.choose(0).unwrap();
+            runtime.choose(1).unwrap();
+        }
+        assert_eq!(runtime.history().len(), 2);
+        assert_eq!(runtime.history()[0].scene_id, "start");
+    }
+
+    #[test]
+    fn test_strict_mode_reports_hidden_choices() {
+        let mut vault = vault(&[
+            ("start", "{if: player.curiosity > 20}[[end|Secret]]\n[[end|Leave]]"),
+            ("end", "Done."),
+        ]);
+        vault.manifest.undefined_variables = UndefinedPolicy::Strict;
+        let runtime = Runtime::new(vault, "start").unwrap();
+
+        let labels: Vec<_> = runtime.available_choices().iter().map(|(_, c)| c.label.as_str()).collect();
+        assert_eq!(labels, vec!["Leave"]);
+        runtime.available_choices();
+
+        let diagnostics = runtime.take_diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "undefined-variable");
+        assert_eq!(diagnostics[0].span.as_ref().unwrap().column, 6);
+        assert!(runtime.take_diagnostics().is_empty());
+    }
+
+    #[test]
+    fn test_undefined_policy_override() {
+        let vault = vault(&[("start", "[[start|Wait]]\n{if: gold > 1}[[start|Buy]]")]);
+        assert_eq!(vault.manifest.undefined_variables, UndefinedPolicy::Default);
+        let mut runtime = Runtime::new(vault, "start").unwrap();
+        runtime.choose(0).unwrap();
+
+        runtime.set_undefined_policy(UndefinedPolicy::Strict);
+        runtime.available_choices();
+        assert_eq!(runtime.take_diagnostics().len(), 1);
+
+        runtime.undo().unwrap();
+        assert_eq!(runtime.state().undefined, UndefinedPolicy::Strict);
+    }
+
+    #[test]
+    fn test_default_mode_treats_missing_as_zero() {
+        let runtime = Runtime::new(
+            vault(&[("start", "{if: player.curiosity < 20}[[end|Timid]]"), ("end", "Done.")]),
+            "start",
+        )
+        .unwrap();
+        assert_eq!(runtime.available_choices().len(), 1);
+        assert!(runtime.take_diagnostics().is_empty());
+    }
+
+    #[test]
+    fn test_failed_choice_changes_nothing() {
+        let mut runtime = Runtime::new(
+            vault(&[("start", "[[end|Go]](gold += 5; gold += \"x\" * 2)\n[[end|Pay]](gold -= 3)"), ("end", "Done.")]),
+            "start",
+        )
+        .unwrap();
+
+        assert!(runtime.choose(0).is_err());
+        assert_eq!(runtime.current_scene_id(), "start");
+        assert_eq!(gold(&runtime), None);
+        assert!(runtime.history().is_empty());
+
+        let changes = runtime.choose(1).unwrap().changes;
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].variable, "gold");
+        assert_eq!(changes[0].before, None);
+        assert_eq!(changes[0].after.as_i64(), Some(-3));
+    }
+
+    #[test]
+    fn test_preview_matches_choose() {
+        let mut runtime = Runtime::new(story(), "start").unwrap();
+
+        let preview = runtime.preview_choice(0).unwrap();
+        assert_eq!(runtime.current_scene_id(), "start");
+        assert_eq!(gold(&runtime), None);
+        assert!(runtime.history().is_empty());
+
+        let outcome = runtime.choose(0).unwrap();
+        assert_eq!(preview, outcome);
+        assert_eq!((outcome.from.as_str(), outcome.to.as_str()), ("start", "middle"));
+        assert_eq!(outcome.changes[0].after.as_i64(), Some(5));
+        assert!(runtime.preview_choice(5).is_err());
+    }
+
+    #[test]
+    fn test_choose_enforces_conditions_and_ids() {
+        let mut runtime = Runtime::new(
+            vault(&[
+                ("start", "{if: gold > 5}[[end|Buy]] ^buy\n[[start|Work]](gold += 5)\n"),
+                ("end", "Done."),
+            ]),
+            "start",
+        )
+        .unwrap();
+
+        assert!(matches!(
+            runtime.choose_by_id("buy"),
+            Err(PackardError::ChoiceUnavailable { ref id, span: Some(_) }) if id == "buy"
+        ));
+        assert!(matches!(runtime.choose(0), Err(PackardError::ChoiceUnavailable { .. })));
+        assert!(matches!(runtime.choose_by_id("nope"), Err(PackardError::ChoiceNotFound { .. })));
+
+        runtime.replay(&["start-work".to_string(), "start-work".to_string(), "buy".to_string()]).unwrap();
+        assert_eq!(runtime.current_scene_id(), "end");
+        assert_eq!(runtime.choice_log(), &["start-work", "start-work", "buy"]);
+        assert_eq!(runtime.history()[2].choice_id, "buy");
+
+        runtime.rewind_to(1).unwrap();
+        assert_eq!(runtime.choice_log(), &["start-work"]);
+    }
+
+    #[test]
+    fn test_visit_counts_and_turns() {
+        let mut runtime = Runtime::new(
+            vault(&[
+                ("start", "[[room|Enter]]\n{if: visited(\"room\") AND turns >= 2}[[end|Leave]](seen = visits(\"room\"))"),
+                ("room", "[[start|Back]]"),
+                ("end", "Done."),
+            ]),
+            "start",
+        )
+        .unwrap();
+        assert_eq!(runtime.state().visits("start"), 1);
+        assert_eq!(runtime.available_choices().len(), 1);
+
+        runtime.choose_by_id("room-enter").unwrap();
+        runtime.choose_by_id("start-back").unwrap();
+        assert_eq!(runtime.state().turns, 2);
+        assert_eq!(runtime.state().visits("start"), 2);
+        assert_eq!(runtime.state().visits("start#^room-enter"), 1);
+
+        runtime.choose_by_id("end-leave").unwrap();
+        assert_eq!(runtime.state().get("seen").unwrap().as_i64(), Some(1));
+
+        runtime.undo().unwrap();
+        assert_eq!(runtime.state().turns, 2);
+        assert_eq!(runtime.state().visits("end"), 0);
+    }
+
+    #[test]
+    fn test_once_only_choices() {
+        let mut runtime = Runtime::new(
+            vault(&[
+                ("start", "{once}[[start|Read]]\n[[start|Wait]]\n{once}{if: turns > 5}[[start|Later]]"),
+                ("other", "[[other|Look]]\n{sticky}[[other|Stay]]"),
+            ]),
+            "start",
+        )
+        .unwrap();
+        let labels = |runtime: &Runtime| -> Vec<String> {
+            runtime.available_choices().iter().map(|(_, c)| c.label.clone()).collect()
+        };
+
+        assert_eq!(labels(&runtime), vec!["Read", "Wait"]);
+        runtime.choose_by_id("start-read").unwrap();
+        assert_eq!(labels(&runtime), vec!["Wait"]);
+        assert!(matches!(runtime.choose_by_id("start-read"), Err(PackardError::ChoiceUnavailable { .. })));
+
+        runtime.undo().unwrap();
+        assert_eq!(labels(&runtime), vec!["Read", "Wait"]);
+
+        let other = &runtime.vault.get_scene("other").unwrap().choices;
+        assert!(!other[0].once && !other[1].once);
+    }
+
+    #[test]
+    fn test_enter_and_exit_effects_run_in_order() {
+        let mut runtime = Runtime::new(
+            vault(&[
+                ("start", "---\non_enter: \"log = ['enter start']\"\non_exit: \"log += 'exit start'\"\n---\n[[room|Go]](log += \"choice\")"),
+                ("room", "---\non_enter: \"log += 'enter room'; entered = visits('room')\"\n---\n[[start|Back]]"),
+            ]),
+            "start",
+        )
+        .unwrap();
+        let log = |runtime: &Runtime| -> Vec<String> {
+            let log = runtime.state().get("log").unwrap().as_sequence().unwrap();
+            log.iter().map(|v| v.as_str().unwrap().to_string()).collect()
+        };
+        assert_eq!(log(&runtime), vec!["enter start"]);
+
+        let outcome = runtime.choose(0).unwrap();
+        assert_eq!(log(&runtime), vec!["enter start", "choice", "exit start", "enter room"]);
+        assert_eq!(runtime.state().get("entered").unwrap().as_i64(), Some(1));
+        let changed: Vec<_> = outcome.changes.iter().map(|c| c.variable.as_str()).collect();
+        assert_eq!(changed, vec!["log", "entered"]);
+    }
+
+    #[test]
+    fn test_failing_enter_effect_blocks_the_choice() {
+        let mut runtime = Runtime::new(
+            vault(&[
+                ("start", "[[trap|Go]](gold = 5)"),
+                ("trap", "---\non_enter: \"gold = gold / 0\"\n---\n[[start|Back]]"),
+            ]),
+            "start",
+        )
+        .unwrap();
+
+        let err = runtime.choose(0).unwrap_err();
+        assert_eq!(err.span().unwrap().file, "trap.md");
+        assert_eq!(runtime.current_scene_id(), "start");
+        assert_eq!(gold(&runtime), None);
+    }
+
+    #[test]
+    fn test_seeded_sessions_replay_exactly() {
+        let story = || {
+            vault(&[
+                ("start", "[[start|Roll]](roll = random(1, 100))\n{if: chance(50)}[[lucky|Try your luck]]"),
+                ("lucky", "---\nending: true\n---\n"),
+            ])
+        };
+        let play = |seed: u64| -> Vec<(i64, usize)> {
+            let mut runtime = Runtime::with_seed(story(), "start", seed).unwrap();
+            (0..8)
+                .map(|_| {
+                    runtime.choose_by_id("start-roll").unwrap();
+                    (runtime.state().get("roll").unwrap().as_i64().unwrap(), runtime.available_choices().len())
+                })
+                .collect()
+        };
+
+        assert_eq!(play(5), play(5));
+        assert_ne!(play(5), play(6));
+        // The luck check is re-rolled every turn rather than fixed for good
+        let offered: Vec<_> = play(5).iter().map(|(_, n)| *n).collect();
+        assert!(offered.contains(&1) && offered.contains(&2));
+    }
+
+    #[test]
+    fn test_random_calls_are_independent() {
+        let story = || {
+            vault(&[(
+                "start",
+                "{if: chance(30)}[[start|Rare]]\n{if: chance(70)}[[start|Common]]\n\
+                 {if: random(1, 6) == random(1, 6)}[[start|Doubles]]\n\
+                 {if: chance(50)}[[start|Gamble]](won = chance(50))",
+            )])
+        };
+        let (mut rare_alone, mut doubles, mut gambles, mut wins) = (0, 0, 0, 0);
+        for seed in 0..200 {
+            let mut runtime = Runtime::with_seed(story(), "start", seed).unwrap();
+            let offered: Vec<&str> = runtime.available_choices().iter().map(|(_, c)| c.label.as_str()).collect();
+            rare_alone += (offered.contains(&"Rare") && !offered.contains(&"Common")) as u32;
+            doubles += offered.contains(&"Doubles") as u32;
+            if offered.contains(&"Gamble") {
+                runtime.choose_by_id("start-gamble").unwrap();
+                gambles += 1;
+                wins += (runtime.state().get("won").unwrap().as_bool() == Some(true)) as u32;
+            }
+        }
+
+        // Each call draws its own number: no roll is nested in or equal to
+        // another, and an effect does not repeat its condition's roll
+        assert!(rare_alone > 0);
+        assert!(doubles < 100, "{}", doubles);
+        assert!(wins > 0 && wins < gambles, "{} of {}", wins, gambles);
+    }
+
+    #[test]
+    fn test_manifest_variables_are_set_before_the_start() {
+        let mut vault = vault(&[("intro", "---\non_enter: \"gold += 1\"\n---\n[[intro|Stay]]")]);
+        vault.manifest.variables.insert("gold".to_string(), serde_yaml::Value::Number(10.into()));
+
+        let runtime = Runtime::new(vault, "intro").unwrap();
+        assert_eq!(gold(&runtime), Some(11));
+    }
+
+    #[test]
+    fn test_heading_links_start_at_the_section() {
+        let mut runtime = Runtime::new(
+            vault(&[
+                ("start", "[[hall#Stairs|Up]]\n[[hall|In]]"),
+                ("hall", "Long hall.\n# Stairs\nSteep stairs.\n[[start|Back]]"),
+            ]),
+            "start",
+        )
+        .unwrap();
+
+        runtime.choose(0).unwrap();
+        assert_eq!(runtime.current_heading(), Some("Stairs"));
+        assert_eq!(runtime.render_scene(), "# Stairs\nSteep stairs.\n");
+
+        runtime.undo().unwrap();
+        assert_eq!(runtime.current_heading(), None);
+        runtime.choose(1).unwrap();
+        assert_eq!(runtime.render_scene(), "Long hall.\n# Stairs\nSteep stairs.\n");
+    }
+
+    #[test]
+    fn test_note_hooks_run_for_its_sub_scenes() {
+        let mut runtime = Runtime::new(
+            vault(&[
+                ("start", "[[hall#Stairs|Up]]"),
+                (
+                    "hall",
+                    "---\non_enter: \"log += 'enter'\"\non_exit: \"log += 'exit'\"\n---\nHall.\n[[#Stairs|Up]]\n## Stairs\n[[hall|Down]]\n[[start|Out]]",
+                ),
+            ]),
+            "start",
+        )
+        .unwrap();
+        let log = |runtime: &Runtime| -> Vec<String> {
+            let log = runtime.state().get("log").unwrap().as_sequence().unwrap();
+            log.iter().map(|v| v.as_str().unwrap().to_string()).collect()
+        };
+
+        runtime.choose(0).unwrap();
+        assert_eq!(runtime.current_scene_id(), "hall#Stairs");
+        assert_eq!(log(&runtime), vec!["enter"]);
+
+        runtime.choose(0).unwrap();
+        assert_eq!(runtime.current_scene_id(), "hall");
+        runtime.choose(0).unwrap();
+        assert_eq!(log(&runtime), vec!["enter"]);
+
+        runtime.choose(1).unwrap();
+        assert_eq!(runtime.current_scene_id(), "start");
+        assert_eq!(log(&runtime), vec!["enter", "exit"]);
+    }
+
+    #[test]
+    fn test_sub_scenes_are_scenes() {
+        let mut runtime = Runtime::new(
+            vault(&[
+                ("start", "[[keeper#Ask about secrets|Ask]]"),
+                ("keeper", "The keeper waits.\n[[#Ask about secrets]]\n## Ask about secrets\n\"Not today.\"\n[[#Leave]]\n## Leave\nYou go.\n[[start|Again]]"),
+            ]),
+            "start",
+        )
+        .unwrap();
+
+        runtime.choose(0).unwrap();
+        assert_eq!(runtime.current_scene_id(), "keeper#Ask about secrets");
+        assert_eq!(runtime.current_heading(), None);
+        assert_eq!(runtime.render_scene(), "\"Not today.\"\n");
+        assert_eq!(runtime.choice_label(runtime.available_choices()[0].1), "Leave");
+
+        runtime.choose(0).unwrap();
+        assert_eq!(runtime.current_scene_id(), "keeper#Leave");
+        assert_eq!(runtime.state().visits("keeper#Leave"), 1);
+    }
+
+    #[test]
+    fn test_dialogue_is_rendered_apart() {
+        let mut vault = vault(&[(
+            "start",
+            "---\nending: true\n---\nThe room is quiet.\n**{who}**: \"Say {line}\"\n{if: false}**Ghost**: \"Boo\"\n{endif}After.",
+        )]);
+        let variables = &mut vault.manifest.variables;
+        variables.insert("who".to_string(), serde_yaml::Value::String("Ada".to_string()));
+        variables.insert("line".to_string(), serde_yaml::Value::String("hi\n**Bob**: \"no\"".to_string()));
+        let runtime = Runtime::new(vault, "start").unwrap();
+
+        let dialogue = runtime.render_dialogue();
+        assert_eq!(dialogue.len(), 1);
+        assert_eq!((dialogue[0].character.as_str(), dialogue[0].text.as_str()), ("Ada", "Say hi\n**Bob**: \"no\""));
+        assert_eq!(runtime.render_narration(), "The room is quiet.\nAfter.");
+    }
+
+    #[test]
+    fn test_choice_labels_are_rendered() {
+        let mut runtime = Runtime::new(
+            vault(&[("start", "You have {gold|0} gold.\n[[start|Spend {gold|0:02} gold]](gold = 7)")]),
+            "start",
+        )
+        .unwrap();
+        let label = |runtime: &Runtime| runtime.choice_label(runtime.available_choices()[0].1);
+
+        assert_eq!(label(&runtime), "Spend 00 gold");
+        runtime.choose(0).unwrap();
+        assert_eq!(label(&runtime), "Spend 07 gold");
+        assert_eq!(runtime.render_scene(), "You have 7 gold.\n");
+        assert!(runtime.take_diagnostics().is_empty());
+    }
 }
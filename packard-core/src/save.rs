@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::error::{PackardError, Result};
+use crate::rng::Rng;
+use crate::vault::Vault;
+
+/// Bumped whenever the save layout changes incompatibly.
+pub const SAVE_VERSION: u32 = 1;
+
+/// A saved game session, written as YAML.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SaveFile {
+    pub version: u32,
+    /// Fingerprint of the vault the save was made against, see [`fingerprint`].
+    pub vault: String,
+    pub scene: String,
+    /// Heading the scene was entered at, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heading: Option<String>,
+    pub variables: BTreeMap<String, serde_yaml::Value>,
+    /// Every scene visited, in order, starting with the start scene.
+    pub history: Vec<String>,
+    /// Id of every choice taken, in order.
+    #[serde(default)]
+    pub choices: Vec<String>,
+    /// Visit counts per scene and per choice (`scene#^choice-id`).
+    #[serde(default)]
+    pub visits: BTreeMap<String, u32>,
+    #[serde(default)]
+    pub turns: u32,
+    /// Seed and position of the random stream. Older saves without it keep
+    /// whatever stream the session already had.
+    #[serde(default)]
+    pub rng: Option<Rng>,
+}
+
+impl SaveFile {
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let yaml = serde_yaml::to_string(self).map_err(|e| PackardError::InvalidSave {
+            path: Some(path.to_path_buf()),
+            message: e.to_string(),
+        })?;
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|source| PackardError::Io { path: dir.to_path_buf(), source })?;
+        }
+        fs::write(path, yaml).map_err(|source| PackardError::Io { path: path.to_path_buf(), source })
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let yaml = fs::read_to_string(path)
+            .map_err(|source| PackardError::Io { path: path.to_path_buf(), source })?;
+        let save: SaveFile = serde_yaml::from_str(&yaml).map_err(|e| PackardError::InvalidSave {
+            path: Some(path.to_path_buf()),
+            message: e.to_string(),
+        })?;
+
+        if save.version != SAVE_VERSION {
+            return Err(PackardError::InvalidSave {
+                path: Some(path.to_path_buf()),
+                message: format!(
+                    "save format version {} is not supported (expected {})",
+                    save.version, SAVE_VERSION
+                ),
+            });
+        }
+
+        Ok(save)
+    }
+}
+
+/// A stable hash of the vault's scene ids and the ids of their choices,
+/// which is what a save's history refers to. Adding, removing or renaming a
+/// scene or choice changes it (so does relabelling a choice without an
+/// explicit `^id`); editing prose or effects does not.
+pub fn fingerprint(vault: &Vault) -> String {
+    // FNV-1a, so the value is the same across builds and platforms
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for id in vault.list_scenes() {
+        let scene = vault.get_scene(&id).unwrap();
+        let choices = scene.choices.iter().map(|c| c.id.as_str());
+        for part in std::iter::once(id.as_str()).chain(choices) {
+            for byte in part.bytes().chain([0]) {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+        // Mark the end of the scene, so a choice id cannot pass for a scene id
+        hash ^= 0xff;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::Runtime;
+    use crate::vault::tests::vault;
+
+    fn story() -> Vault {
+        vault(&[
+            ("start", "[[middle|Go]](gold = 5; brave = true)"),
+            ("middle", "[[end|Finish]]"),
+            ("end", "Done."),
+        ])
+    }
+
+    #[test]
+    fn test_save_and_restore_round_trip() {
+        let path = std::env::temp_dir().join(format!("packard-save-{}.yaml", std::process::id()));
+
+        let mut runtime = Runtime::new(story(), "start").unwrap();
+        runtime.choose(0).unwrap();
+        runtime.save(&path).unwrap();
+
+        let mut restored = Runtime::new(story(), "start").unwrap();
+        restored.restore(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(restored.current_scene_id(), "middle");
+        assert_eq!(restored.state().get("gold").unwrap().as_i64(), Some(5));
+        assert_eq!(restored.state().get("brave").unwrap().as_bool(), Some(true));
+        assert_eq!(restored.visit_history(), &["start".to_string(), "middle".to_string()]);
+        assert_eq!(restored.choice_log(), &["middle-go".to_string()]);
+        assert_eq!(restored.state().visits("middle"), 1);
+        assert_eq!(restored.state().visits("start#^middle-go"), 1);
+        assert_eq!(restored.state().turns, 1);
+    }
+
+    #[test]
+    fn test_lists_survive_save_files() {
+        let path = std::env::temp_dir().join(format!("packard-lists-{}.yaml", std::process::id()));
+        let story = || vault(&[("start", "[[end|Pack]](bag += \"key\"; bag += \"lamp\")"), ("end", "Done.")]);
+
+        let mut runtime = Runtime::new(story(), "start").unwrap();
+        runtime.choose(0).unwrap();
+        runtime.save(&path).unwrap();
+
+        let mut restored = Runtime::new(story(), "start").unwrap();
+        restored.restore(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(restored.state().get("bag"), runtime.state().get("bag"));
+        assert_eq!(restored.state().get("bag").unwrap().as_sequence().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_random_stream_survives_save_files() {
+        let path = std::env::temp_dir().join(format!("packard-rng-{}.yaml", std::process::id()));
+        let story = || vault(&[("start", "[[start|Roll]](roll = random(1, 1000))")]);
+
+        let mut runtime = Runtime::with_seed(story(), "start", 99).unwrap();
+        runtime.choose(0).unwrap();
+        runtime.save(&path).unwrap();
+        runtime.choose(0).unwrap();
+
+        let mut restored = Runtime::new(story(), "start").unwrap();
+        restored.restore(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(restored.seed(), 99);
+
+        restored.choose(0).unwrap();
+        assert_eq!(restored.state().get("roll"), runtime.state().get("roll"));
+    }
+
+    #[test]
+    fn test_restore_rejects_changed_vault() {
+        let mut runtime = Runtime::new(story(), "start").unwrap();
+        runtime.choose(0).unwrap();
+        let save = runtime.snapshot();
+
+        let edited = vault(&[
+            ("start", "[[middle|Go]](gold = 10)\n[[end|Skip]]"),
+            ("middle", "[[end|Finish]]"),
+            ("end", "Done."),
+        ]);
+        let mut other = Runtime::new(edited, "start").unwrap();
+        match other.restore_snapshot(save) {
+            Err(PackardError::InvalidSave { message, .. }) => assert!(message.contains("changed")),
+            other => panic!("Expected InvalidSave, got {:?}", other),
+        }
+        assert_eq!(other.current_scene_id(), "start");
+    }
+
+    #[test]
+    fn test_restore_survives_prose_edits() {
+        let mut runtime = Runtime::new(story(), "start").unwrap();
+        runtime.choose(0).unwrap();
+        let save = runtime.snapshot();
+
+        let edited = vault(&[
+            ("start", "A typo fixed.\n[[middle|Go]](gold = 10)"),
+            ("middle", "[[end|Finish]]"),
+            ("end", "All done."),
+        ]);
+        let mut other = Runtime::new(edited, "start").unwrap();
+        other.restore_snapshot(save).unwrap();
+        assert_eq!(other.current_scene_id(), "middle");
+    }
+
+    #[test]
+    fn test_read_rejects_unknown_version() {
+        let path = std::env::temp_dir().join(format!("packard-version-{}.yaml", std::process::id()));
+        let mut save = Runtime::new(story(), "start").unwrap().snapshot();
+        save.version = SAVE_VERSION + 1;
+        save.write(&path).unwrap();
+
+        let result = SaveFile::read(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(PackardError::InvalidSave { .. })));
+    }
+}
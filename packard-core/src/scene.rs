@@ -1,117 +1,520 @@
-use regex::Regex;
+use std::collections::HashMap;
 use crate::effects::Effect;
 use crate::conditions::Condition;
 use crate::dialogue::DialogueLine;
+use crate::check::Diagnostic;
+use crate::error::Result;
+use crate::parser::{self, Span};
+use crate::template::Template;
 
 #[derive(Debug, Clone)]
 pub struct Scene {
+    /// Shortest path that names this note unambiguously, e.g. `intro` or,
+    /// when several notes are called that, `act1/intro`.
     pub id: String,
+    /// Full path in the vault without `.md`, e.g. `act1/intro`.
+    pub path: String,
     pub title: String,
+    /// Other names the scene can be linked by, from frontmatter `aliases`.
+    pub aliases: Vec<String>,
     pub content: String,
+    /// The body as renderable prose, without choice markup.
+    pub prose: Template,
+    /// The prose from each heading on, for `[[scene#Heading]]` links to
+    /// headings that do not start a sub-scene.
+    pub sections: Vec<Section>,
+    /// Scenes for the parts the note's `##` headings split it into, with ids
+    /// `note#Heading`, or `note#Heading-2` and so on for a repeated heading.
+    /// `Vault::load` moves them into its own scene map.
+    pub subscenes: Vec<Scene>,
+    /// Ids of the sub-scenes in the order they appear, kept after
+    /// `Vault::load` has moved them out of `subscenes`.
+    pub parts: Vec<String>,
     pub choices: Vec<Choice>,
     pub dialogue: Vec<DialogueLine>,
+    /// Set by `ending: true` in frontmatter; endings may have no choices.
+    /// In a note split into sub-scenes it marks only the parts without any.
+    pub ending: bool,
+    /// Frontmatter `on_enter` effects, run whenever the scene is entered.
+    /// Only a note's own scene has them; they run when play enters any of
+    /// its sub-scenes from outside the note, but not between its parts.
+    pub on_enter: Vec<Effect>,
+    /// Frontmatter `on_exit` effects, run whenever a choice leaves the scene,
+    /// or for a note with sub-scenes, leaves the note.
+    pub on_exit: Vec<Effect>,
+    pub on_enter_span: Option<Span>,
+    pub on_exit_span: Option<Span>,
+    pub file: String,
+    /// Markup problems found while parsing (bad effect or condition syntax).
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// A scene's prose starting at one of its headings.
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub heading: String,
+    pub prose: Template,
 }
 
 #[derive(Debug, Clone)]
 pub struct Choice {
+    /// Stable identifier, unique within the scene: the block id written after
+    /// the link (`^take-key`) or else derived from the target and label.
+    pub id: String,
     pub target: String,
+    /// Heading in the target to start at, from `[[target#Heading]]`
+    pub heading: Option<String>,
+    /// For a bare `[[target]]` link, the target's title once the vault is loaded
     pub label: String,
+    /// The label with its `{...}` placeholders, for `Runtime::choice_label`
+    pub label_template: Template,
     pub effects: Vec<Effect>,
     pub condition: Option<Condition>,
+    /// Once-only choices disappear after being taken; others are sticky.
+    pub once: bool,
+    pub span: Span,
+    pub condition_span: Option<Span>,
+    pub effects_span: Option<Span>,
 }
 
 impl Scene {
-    pub fn from_markdown(id: String, content: &str) -> Result<Self, String> {
-        // Split frontmatter from content
-        let parts: Vec<&str> = content.splitn(3, "---").collect();
-        
-        let (frontmatter, body) = if parts.len() == 3 {
-            (parts[1], parts[2])
-        } else {
-            ("", content)
-        };
+    /// The section under `heading`, matched regardless of case.
+    pub fn section(&self, heading: &str) -> Option<&Section> {
+        self.sections.iter().find(|s| s.heading.to_lowercase() == heading.to_lowercase())
+    }
 
-        // Parse YAML frontmatter
-        let mut title = id.clone();
-        if !frontmatter.is_empty() {
-            if let Ok(data) = serde_yaml::from_str::<serde_yaml::Value>(frontmatter) {
-                if let Some(title_val) = data.get("title") {
-                    if let Some(title_str) = title_val.as_str() {
-                        title = title_str.to_string();
-                    }
-                }
+    /// Scene names passed to `visited` or `visits` anywhere in the scene.
+    pub fn scene_names(&self) -> Vec<&str> {
+        let templates = std::iter::once(&self.prose).chain(self.choices.iter().map(|c| &c.label_template));
+        let conditions = templates
+            .flat_map(|t| t.conditions())
+            .map(|(condition, _)| condition)
+            .chain(self.choices.iter().filter_map(|c| c.condition.as_ref()));
+        let effects = self.choices.iter().flat_map(|c| &c.effects).chain(&self.on_enter).chain(&self.on_exit);
+        conditions
+            .flat_map(|c| c.scene_names())
+            .chain(effects.flat_map(|e| e.expr.scene_names()))
+            .collect()
+    }
+
+    /// Point `visited` and `visits` at the scene ids the vault resolved
+    /// their names to.
+    pub(crate) fn rename_scenes(&mut self, ids: &HashMap<String, String>) {
+        self.prose.rename_scenes(ids);
+        for section in &mut self.sections {
+            section.prose.rename_scenes(ids);
+        }
+        for choice in &mut self.choices {
+            choice.label_template.rename_scenes(ids);
+            if let Some(condition) = &mut choice.condition {
+                condition.rename_scenes(ids);
+            }
+            for effect in &mut choice.effects {
+                effect.expr.rename_scenes(ids);
             }
         }
+        for effect in self.on_enter.iter_mut().chain(&mut self.on_exit) {
+            effect.expr.rename_scenes(ids);
+        }
+    }
+}
 
-        // Parse all wikilinks and conditionals: {if: condition}[[target|label]](effects) or [[target|label]](effects)
-        let choice_re = Regex::new(r"\{if:\s*([^}]+)\}?\[\[([^\]|]+)\|([^\]]+)\]\](?:\(([^)]*)\))?").unwrap();
-        let mut choices = Vec::new();
-        let mut processed_positions = std::collections::HashSet::new();
-
-        for cap in choice_re.captures_iter(body) {
-            let condition_str = cap.get(1).as_ref().map(|c| c.as_str());
-            let target = cap.get(2).unwrap().as_str().to_string();
-            let label = cap.get(3).unwrap().as_str().to_string();
-            
-            let condition = condition_str.and_then(|s| crate::conditions::parse_condition(s).ok());
-            
-            let effects = if let Some(effects_str) = cap.get(4) {
-                crate::effects::parse_effects(effects_str.as_str()).unwrap_or_default()
-            } else {
-                Vec::new()
+/// Where `[[note#heading]]` leads, given `parts`: the note itself followed
+/// by its sub-scenes in order. That is the sub-scene the heading starts, or
+/// else the first part with a section under the heading, returned with
+/// `true` to say the link still starts at that section. Headings match
+/// regardless of case.
+pub(crate) fn heading_target<'a>(parts: &[&'a Scene], heading: &str) -> Option<(&'a Scene, bool)> {
+    let note = parts.first()?;
+    let anchor = format!("{}#{}", note.id, heading).to_lowercase();
+    if let Some(sub) = parts[1..].iter().find(|s| s.id.to_lowercase() == anchor) {
+        return Some((sub, false));
+    }
+    parts.iter().find(|s| s.section(heading).is_some()).map(|s| (*s, true))
+}
+
+impl Choice {
+    /// Key under which taking this choice is counted in `State::visits`.
+    pub fn visit_key(&self, scene_id: &str) -> String {
+        format!("{}#^{}", scene_id, self.id)
+    }
+}
+
+impl Scene {
+    pub fn from_markdown(id: String, content: &str) -> Result<Self> {
+        let file = format!("{}.md", id);
+        Self::from_source(id, content, &file)
+    }
+
+    /// Parse a note, recording `file` in the spans of its choices. Every
+    /// heading of level 2 or deeper starts a sub-scene, returned in
+    /// `subscenes`; the scene itself is what comes before the first one.
+    pub fn from_source(id: String, content: &str, file: &str) -> Result<Self> {
+        let (frontmatter, _, body_start) = parser::split_frontmatter(content);
+        let source = parser::Source::new(file, content);
+
+        // Parse YAML frontmatter
+        let mut title = id.rsplit('/').next().unwrap_or(&id).to_string();
+        let mut aliases = Vec::new();
+        let mut ending = false;
+        let mut diagnostics = Vec::new();
+        let mut hooks: [(Vec<Effect>, Option<Span>); 2] = Default::default();
+        if let Some(frontmatter) = frontmatter {
+            let data = parser::parse_frontmatter(&source, frontmatter)?;
+            if let Some(title_val) = data.get("title") {
+                if let Some(title_str) = title_val.as_str() {
+                    title = title_str.to_string();
+                }
+            }
+            // A single alias may be written without a list
+            aliases = match data.get("aliases").or_else(|| data.get("alias")) {
+                Some(serde_yaml::Value::String(alias)) => vec![alias.clone()],
+                Some(serde_yaml::Value::Sequence(list)) => {
+                    list.iter().filter_map(|a| a.as_str()).map(str::to_string).collect()
+                }
+                _ => Vec::new(),
             };
+            ending = data.get("ending").and_then(|v| v.as_bool()).unwrap_or(false);
 
-            // Track position to avoid duplicates
-            if let Some(pos) = cap.get(0) {
-                processed_positions.insert(pos.start());
+            for (key, (effects, span)) in ["on_enter", "on_exit"].into_iter().zip(hooks.iter_mut()) {
+                let Some(value) = data.get(key) else { continue };
+                *span = parser::frontmatter_key_span(&source, frontmatter, key);
+                let parsed = match value.as_str() {
+                    Some(text) => crate::effects::parse_effects(text),
+                    None => Err(crate::error::PackardError::syntax(format!("{} must be a string of effects", key))),
+                };
+                *effects = parsed.unwrap_or_else(|err| {
+                    let err = match span {
+                        Some(span) => err.with_span(span),
+                        None => err,
+                    };
+                    diagnostics.push(Diagnostic::from(err));
+                    Vec::new()
+                });
             }
+        }
+        let [(on_enter, on_enter_span), (on_exit, on_exit_span)] = hooks;
 
-            choices.push(Choice { 
-                target, 
-                label, 
-                effects,
-                condition,
-            });
+        // The shallowest heading level below the title, usually `##`, splits
+        // the note; each part runs to the next and keeps deeper headings as
+        // sections
+        let headings = parser::scan_headings(&source, body_start, content.len());
+        let level = headings.iter().map(|h| h.level).filter(|&level| level >= 2).min();
+        let splits: Vec<parser::Heading> = headings.into_iter().filter(|h| Some(h.level) == level).collect();
+        let part_end = |i: usize| splits.get(i).map_or(content.len(), |h| h.start);
+
+        let mut scene = Scene::from_part(&id, title, &source, body_start, part_end(0));
+        scene.aliases = aliases;
+        scene.ending = ending;
+        scene.on_enter = on_enter;
+        scene.on_exit = on_exit;
+        scene.on_enter_span = on_enter_span;
+        scene.on_exit_span = on_exit_span;
+        diagnostics.append(&mut scene.diagnostics);
+
+        for (i, heading) in splits.iter().enumerate() {
+            // A repeated heading is numbered to keep its id apart
+            let taken = |anchor: &str| {
+                let sub_id = format!("{}#{}", id, anchor).to_lowercase();
+                scene.subscenes.iter().any(|s| s.id.to_lowercase() == sub_id)
+            };
+            let mut anchor = heading.text.clone();
+            if taken(&anchor) {
+                anchor = (2..).map(|n| format!("{}-{}", heading.text, n)).find(|a| !taken(a)).unwrap();
+                diagnostics.push(
+                    Diagnostic::warning(
+                        "duplicate-heading",
+                        format!("heading '{}' is used more than once; link to this one as [[{}#{}]]", heading.text, id, anchor),
+                    )
+                    .with_span(Some(&source.span(heading.start, heading.start))),
+                );
+            }
+            let sub_id = format!("{}#{}", id, anchor);
+            let text_start = content[heading.start..].find('\n').map_or(content.len(), |n| heading.start + n + 1);
+            let mut sub = Scene::from_part(&id, heading.text.clone(), &source, text_start.min(part_end(i + 1)), part_end(i + 1));
+            sub.id = sub_id;
+            sub.path = sub.id.clone();
+            sub.ending = ending && sub.choices.is_empty();
+            diagnostics.append(&mut sub.diagnostics);
+            scene.subscenes.push(sub);
+        }
+        if !scene.subscenes.is_empty() {
+            scene.ending = ending && scene.choices.is_empty();
         }
 
-        // Also parse simple unconditional wikilinks: [[target|label]](effects)
-        let wikilink_re = Regex::new(r"\[\[([^\]|]+)\|([^\]]+)\]\](?:\(([^)]*)\))?").unwrap();
-        
-        for cap in wikilink_re.captures_iter(body) {
-            // Skip if this was already captured by the conditional regex
-            if let Some(pos) = cap.get(0) {
-                let text = pos.as_str();
-                if text.contains("{if:") {
-                    continue;
+        scene.parts = scene.subscenes.iter().map(|sub| sub.id.clone()).collect();
+
+        // Links to a heading of this note lead to its sub-scene, or into the
+        // sub-scene the heading is a section of
+        let parts: Vec<&Scene> = std::iter::once(&scene).chain(&scene.subscenes).collect();
+        let links: Vec<(usize, usize, String, Option<String>)> = parts
+            .iter()
+            .enumerate()
+            .flat_map(|(p, part)| part.choices.iter().enumerate().map(move |(i, choice)| (p, i, choice)))
+            .filter(|(_, _, choice)| choice.target == id)
+            .filter_map(|(p, i, choice)| {
+                let (target, section) = heading_target(&parts, choice.heading.as_deref()?)?;
+                Some((p, i, target.id.clone(), (!section).then(|| target.title.clone())))
+            })
+            .collect();
+        for (p, i, target, title) in links {
+            let part = if p == 0 { &mut scene } else { &mut scene.subscenes[p - 1] };
+            let choice = &mut part.choices[i];
+            if let Some(title) = title {
+                if choice.label.is_empty() {
+                    choice.label_template = Template::text(&title);
+                    choice.label = title;
                 }
+                choice.heading = None;
             }
-            
-            let target = cap.get(1).unwrap().as_str().to_string();
-            let label = cap.get(2).unwrap().as_str().to_string();
-            
-            let effects = if let Some(effects_str) = cap.get(3) {
-                crate::effects::parse_effects(effects_str.as_str()).unwrap_or_default()
-            } else {
-                Vec::new()
-            };
-
-            choices.push(Choice { 
-                target, 
-                label, 
-                effects,
-                condition: None,
-            });
+            choice.target = target;
         }
 
+        scene.diagnostics = diagnostics;
+        Ok(scene)
+    }
+
+    /// The scene for `source.text[start..end]` of note `note`, without any
+    /// of the note's frontmatter settings.
+    fn from_part(note: &str, title: String, source: &parser::Source, start: usize, end: usize) -> Scene {
+        let mut diagnostics = Vec::new();
+
+        // Parse choices: {if: condition}[[target|label]](effects) or [[target|label]](effects)
+        let (markups, errors) = parser::scan_choices(source, start, end);
+        diagnostics.extend(errors.into_iter().map(Diagnostic::from));
+        let choice_spans: Vec<Span> = markups.iter().map(|m| m.span.clone()).collect();
+        let choices: Vec<(Choice, bool)> = markups
+            .into_iter()
+            .map(|markup| {
+                let condition = markup.condition.as_ref().and_then(|c| {
+                    crate::conditions::parse_condition(&c.text)
+                        .map_err(|e| diagnostics.push(Diagnostic::from(e.with_span(&c.span))))
+                        .ok()
+                });
+
+                let effects = markup
+                    .effects
+                    .as_ref()
+                    .map(|e| {
+                        crate::effects::parse_effects(&e.text).unwrap_or_else(|err| {
+                            diagnostics.push(Diagnostic::from(err.with_span(&e.span)));
+                            Vec::new()
+                        })
+                    })
+                    .unwrap_or_default();
+
+                let (label_template, errors) = Template::parse_fragment(source, &markup.label_span);
+                diagnostics.extend(errors.into_iter().map(Diagnostic::from));
+
+                let explicit = markup.block_id.is_some();
+                let link = match &markup.heading {
+                    Some(heading) => format!("{}#{}", markup.target, heading),
+                    None => markup.target.clone(),
+                };
+                let id = markup
+                    .block_id
+                    .unwrap_or_else(|| parser::slugify(&format!("{} {}", link, markup.label)));
+
+                // `[[#Heading]]` links within the note
+                let target = match markup.target.as_str() {
+                    "" => note.to_string(),
+                    _ => markup.target,
+                };
+
+                let choice = Choice {
+                    id,
+                    target,
+                    heading: markup.heading,
+                    label: markup.label,
+                    label_template,
+                    effects,
+                    condition,
+                    once: markup.once.unwrap_or(false),
+                    condition_span: markup.condition.map(|c| c.span),
+                    effects_span: markup.effects.map(|e| e.span),
+                    span: markup.span,
+                };
+                (choice, explicit)
+            })
+            .collect();
+
+        // Explicit ids must be unique; derived ones are numbered to make them so
+        let mut seen = std::collections::HashSet::new();
+        let choices = choices
+            .into_iter()
+            .map(|(mut choice, explicit)| {
+                if !seen.insert(choice.id.clone()) {
+                    if explicit {
+                        diagnostics.push(
+                            Diagnostic::error("duplicate-choice-id", format!("choice id '{}' is used more than once", choice.id))
+                                .with_span(Some(&choice.span)),
+                        );
+                    } else {
+                        choice.id = (2..).map(|n| format!("{}-{}", choice.id, n)).find(|id| !seen.contains(id)).unwrap();
+                        seen.insert(choice.id.clone());
+                    }
+                }
+                choice
+            })
+            .collect();
+
+        let (prose, errors) = Template::parse(source, start, end, &choice_spans);
+        diagnostics.extend(errors.into_iter().map(Diagnostic::from));
+
+        // Markup errors were reported with the whole prose, so are not repeated
+        let sections = parser::scan_headings(source, start, end)
+            .into_iter()
+            .map(|heading| {
+                let skip: Vec<Span> = choice_spans.iter().filter(|s| s.start >= heading.start).cloned().collect();
+                let (prose, _) = Template::parse(source, heading.start, end, &skip);
+                Section { heading: heading.text, prose }
+            })
+            .collect();
+
         // Extract dialogue from content
-        let dialogue = crate::dialogue::extract_dialogue(body);
+        let content = &source.text[start..end];
+        let dialogue = crate::dialogue::extract_dialogue(content);
 
-        Ok(Scene {
-            id,
+        Scene {
+            id: note.to_string(),
+            path: note.to_string(),
             title,
-            content: body.to_string(),
+            aliases: Vec::new(),
+            content: content.to_string(),
+            prose,
+            sections,
+            subscenes: Vec::new(),
+            parts: Vec::new(),
             choices,
             dialogue,
-        })
+            ending: false,
+            on_enter: Vec::new(),
+            on_exit: Vec::new(),
+            on_enter_span: None,
+            on_exit_span: None,
+            file: source.file.to_string(),
+            diagnostics,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conditional_choice_not_duplicated() {
+        let content = r#"---
+title: Room
+---
+[[journal|Read the journal]](player.curiosity += 15)
+{if: player.curiosity > 20}[[secret|Find a secret passage]](player.wisdom = 100)
+[[start|Go back]]
+"#;
+        let scene = Scene::from_source("room".to_string(), content, "room.md").unwrap();
+        assert_eq!(scene.title, "Room");
+
+        let targets: Vec<_> = scene.choices.iter().map(|c| c.target.as_str()).collect();
+        assert_eq!(targets, vec!["journal", "secret", "start"]);
+        assert!(scene.choices[1].condition.is_some());
+        assert_eq!(scene.choices[1].span.line, 5);
+        assert_eq!(scene.choices[1].condition_span.as_ref().unwrap().column, 6);
+        assert_eq!(scene.choices[1].effects_span.as_ref().unwrap().file, "room.md");
+    }
+
+    #[test]
+    fn test_invalid_frontmatter_is_yaml_error() {
+        let content = "---\ntitle: Room\nbad: [unclosed\n---\nBody";
+        match Scene::from_source("room".to_string(), content, "room.md") {
+            Err(crate::error::PackardError::Yaml { span: Some(span), .. }) => {
+                assert_eq!(span.file, "room.md");
+                assert!(span.line >= 3);
+            }
+            other => panic!("Expected YAML error, got {:?}", other.map(|s| s.id)),
+        }
+    }
+
+    #[test]
+    fn test_choice_ids() {
+        let content = "[[key|Take the key]]\n[[start|Go back]] ^back\n[[key|Take the key]]\n[[end|Leave]] ^back";
+        let scene = Scene::from_source("room".to_string(), content, "room.md").unwrap();
+
+        let ids: Vec<_> = scene.choices.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, vec!["key-take-the-key", "back", "key-take-the-key-2", "back"]);
+        assert_eq!(scene.diagnostics.len(), 1);
+        assert_eq!(scene.diagnostics[0].code, "duplicate-choice-id");
+        assert_eq!(scene.diagnostics[0].span.as_ref().unwrap().line, 4);
+    }
+
+    #[test]
+    fn test_enter_and_exit_effects() {
+        let content = "---\ntitle: Secret\non_enter: \"seen = true; wisdom += 10\"\non_exit: \"oops +\"\n---\n[[start|Back]]";
+        let scene = Scene::from_source("secret".to_string(), content, "secret.md").unwrap();
+
+        let variables: Vec<_> = scene.on_enter.iter().map(|e| e.variable.as_str()).collect();
+        assert_eq!(variables, vec!["seen", "wisdom"]);
+        assert_eq!(scene.on_enter_span.as_ref().unwrap().line, 3);
+        assert!(scene.on_exit.is_empty());
+        assert_eq!(scene.diagnostics.len(), 1);
+        assert_eq!(scene.diagnostics[0].span.as_ref().unwrap().line, 4);
+    }
+
+    #[test]
+    fn test_sub_scenes_split_at_headings() {
+        let scene = Scene::from_markdown(
+            "keeper".to_string(),
+            "---\nending: true\n---\nHe waits.\n# Aside\n[[#Secrets|Ask]]\n## Secrets\n**Keeper**: None.\n[[#leave]]\n## Leave\nBye.\n## Leave\nAgain.\n[[#Leave-2]]\n",
+        )
+        .unwrap();
+
+        assert_eq!(scene.content, "He waits.\n# Aside\n[[#Secrets|Ask]]\n");
+        assert_eq!(scene.sections[0].heading, "Aside");
+        assert_eq!(scene.choices[0].target, "keeper#Secrets");
+        assert_eq!(scene.choices[0].heading, None);
+
+        let subs: Vec<_> = scene.subscenes.iter().map(|s| (s.id.as_str(), s.title.as_str(), s.content.as_str())).collect();
+        assert_eq!(
+            subs,
+            vec![
+                ("keeper#Secrets", "Secrets", "**Keeper**: None.\n[[#leave]]\n"),
+                ("keeper#Leave", "Leave", "Bye.\n"),
+                ("keeper#Leave-2", "Leave", "Again.\n[[#Leave-2]]\n"),
+            ]
+        );
+        assert_eq!(scene.parts, vec!["keeper#Secrets", "keeper#Leave", "keeper#Leave-2"]);
+        assert_eq!(scene.subscenes[0].dialogue.len(), 1);
+        assert!(!scene.ending && !scene.subscenes[0].ending);
+        assert!(scene.subscenes[1].ending && !scene.subscenes[2].ending);
+        let choice = &scene.subscenes[0].choices[0];
+        assert_eq!((choice.target.as_str(), choice.label.as_str()), ("keeper#Leave", "Leave"));
+
+        assert_eq!(scene.subscenes[2].choices[0].target, "keeper#Leave-2");
+
+        let codes: Vec<_> = scene.diagnostics.iter().map(|d| d.code).collect();
+        assert_eq!(codes, vec!["duplicate-heading"]);
+        assert!(scene.diagnostics[0].message.contains("[[keeper#Leave-2]]"));
+    }
+
+    #[test]
+    fn test_deeper_headings_stay_in_their_sub_scene() {
+        let scene = Scene::from_markdown(
+            "keeper".to_string(),
+            "# Keeper\n[[#Details]]\n## Ask\nHe talks.\n### Details\nAt length.\n[[#Ask]]\n## Leave\nBye.\n[[start|Out]]\n",
+        )
+        .unwrap();
+
+        let subs: Vec<_> = scene.subscenes.iter().map(|s| (s.id.as_str(), s.content.as_str())).collect();
+        assert_eq!(
+            subs,
+            vec![
+                ("keeper#Ask", "He talks.\n### Details\nAt length.\n[[#Ask]]\n"),
+                ("keeper#Leave", "Bye.\n[[start|Out]]\n"),
+            ]
+        );
+        assert_eq!(scene.subscenes[0].choices.len(), 1);
+        assert!(scene.subscenes[0].section("details").is_some());
+
+        let choice = &scene.choices[0];
+        assert_eq!((choice.target.as_str(), choice.heading.as_deref()), ("keeper#Ask", Some("Details")));
     }
 }
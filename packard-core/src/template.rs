@@ -0,0 +1,812 @@
+//! Scene prose with inline logic. `{if: condition}...{else}...{endif}` blocks,
+//! `{player.name}` placeholders and `{&first|second}` variations are parsed
+//! once at load time and rendered against the current state; choice markup is
+//! left out of the rendered text.
+
+use serde_yaml::Value;
+use crate::conditions::{parse_condition, Condition};
+use crate::effects::State;
+use crate::error::PackardError;
+use crate::expr::{parse_expr, Expr};
+use crate::parser::{self, find_closing, Source, Span};
+use crate::rng::{stable_hash, Rng};
+use crate::vault::Vault;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// Parsed scene prose, ready to render.
+#[derive(Debug, Clone, Default)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    /// `{path|default:format}`
+    Placeholder(Placeholder),
+    /// `{&a|b|c}` and friends: one option shown per visit to the scene
+    Variation {
+        kind: Variation,
+        span: Span,
+        options: Vec<Vec<Node>>,
+    },
+    /// A condition that failed to parse is `None` and renders the else branch.
+    If {
+        condition: Option<Box<Condition>>,
+        span: Span,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+}
+
+/// How a variation picks its option from the number of times the scene has
+/// been visited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variation {
+    /// `{&a|b|c}`: each option in turn, then the last one for good
+    Sequence,
+    /// `{@a|b|c}`: each option in turn, starting over after the last
+    Cycle,
+    /// `{!a|b|c}`: each option in turn, then nothing
+    Once,
+    /// `{~a|b|c}`: options in a seeded random order, all of them before any
+    /// repeats
+    Shuffle,
+}
+
+impl Variation {
+    fn from_sigil(sigil: char) -> Option<Variation> {
+        match sigil {
+            '&' => Some(Variation::Sequence),
+            '@' => Some(Variation::Cycle),
+            '!' => Some(Variation::Once),
+            '~' => Some(Variation::Shuffle),
+            _ => None,
+        }
+    }
+
+    /// The option to show on the `visit`th visit (counting from 0), if any.
+    /// `seed` fixes the shuffle order.
+    fn pick(self, visit: usize, len: usize, seed: u64) -> Option<usize> {
+        match self {
+            Variation::Sequence => Some(visit.min(len - 1)),
+            Variation::Cycle => Some(visit % len),
+            Variation::Once => (visit < len).then_some(visit),
+            Variation::Shuffle => {
+                // Each pass through the options gets its own order
+                let mut rng = Rng::new(seed.wrapping_add((visit / len) as u64));
+                let mut order: Vec<usize> = (0..len).collect();
+                for i in (1..len).rev() {
+                    order.swap(i, rng.range(0, i as i64) as usize);
+                }
+                Some(order[visit % len])
+            }
+        }
+    }
+}
+
+/// Find the options of a variation starting at the `{` at `start`: their
+/// byte ranges and the offset just past the closing `}`. Unlike conditions,
+/// prose is full of apostrophes, so quotes are not treated specially.
+fn scan_variation(text: &str, start: usize, line_end: usize) -> Option<(Vec<(usize, usize)>, usize)> {
+    let mut options = Vec::new();
+    let mut depth = 0;
+    let mut option_start = start + 2;
+
+    for (offset, ch) in text[start + 2..line_end].char_indices() {
+        let pos = start + 2 + offset;
+        match ch {
+            '{' => depth += 1,
+            '}' if depth == 0 => {
+                options.push((option_start, pos));
+                return Some((options, pos + 1));
+            }
+            '}' => depth -= 1,
+            '|' if depth == 0 => {
+                options.push((option_start, pos));
+                option_start = pos + 1;
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// A value to interpolate: a state variable or, for `characters.<id>.<key>`,
+/// a character property.
+#[derive(Debug, Clone)]
+pub struct Placeholder {
+    pub path: String,
+    /// Shown when the value does not exist
+    pub default: Option<Value>,
+    pub format: Option<Format>,
+    pub span: Span,
+}
+
+/// How a placeholder's value is written out, from the text after `:`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `:upper`
+    Upper,
+    /// `:lower`
+    Lower,
+    /// `:title`, capitalising each word
+    Title,
+    /// `:5`, `:03`, `:.2` or `:08.2`: minimum width, zero padding and
+    /// decimal places for numbers
+    Number { zero: bool, width: usize, precision: Option<usize> },
+}
+
+impl Format {
+    fn parse(spec: &str) -> Option<Format> {
+        match spec {
+            "upper" => return Some(Format::Upper),
+            "lower" => return Some(Format::Lower),
+            "title" => return Some(Format::Title),
+            _ => {}
+        }
+        let (width, precision) = match spec.split_once('.') {
+            Some((width, precision)) => (width, Some(precision.parse().ok()?)),
+            None => (spec, None),
+        };
+        let zero = width.starts_with('0');
+        let width = if width.is_empty() { 0 } else { width.parse().ok()? };
+        Some(Format::Number { zero, width, precision })
+    }
+
+    fn apply(self, value: &Value) -> String {
+        let text = display(value);
+        match self {
+            Format::Upper => text.to_uppercase(),
+            Format::Lower => text.to_lowercase(),
+            Format::Title => text
+                .split(' ')
+                .map(|word| {
+                    let mut chars = word.chars();
+                    match chars.next() {
+                        Some(first) => first.to_uppercase().chain(chars).collect(),
+                        None => String::new(),
+                    }
+                })
+                .collect::<Vec<String>>()
+                .join(" "),
+            Format::Number { zero, width, precision } => {
+                let text = match (precision, value.as_f64()) {
+                    (Some(precision), Some(n)) => format!("{:.*}", precision, n),
+                    _ => text,
+                };
+                if zero && value.is_number() {
+                    let (sign, digits) = match text.strip_prefix('-') {
+                        Some(digits) => ("-", digits),
+                        None => ("", text.as_str()),
+                    };
+                    format!("{}{:0>width$}", sign, digits, width = width.saturating_sub(sign.len()))
+                } else {
+                    format!("{:>width$}", text, width = width)
+                }
+            }
+        }
+    }
+}
+
+/// How a value reads in prose: strings as-is, lists joined with commas.
+fn display(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => s.clone(),
+        Value::Sequence(items) => items.iter().map(display).collect::<Vec<_>>().join(", "),
+        other => crate::expr::format_value(other),
+    }
+}
+
+/// Parse `{path|default:format}` starting at the `{` at `start`. Returns
+/// `None` when the braces do not hold a placeholder, leaving them as text.
+fn parse_placeholder(source: &Source, start: usize, line_end: usize) -> Option<(Result<Placeholder, PackardError>, usize)> {
+    let text = source.text;
+    let close = find_closing(text, start + 1, line_end, '{', '}')?;
+    let inner = &text[start + 1..close];
+    let span = source.span(start, close + 1);
+
+    let path_len = inner
+        .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+        .unwrap_or(inner.len());
+    let path = &inner[..path_len];
+    if !path.starts_with(|c: char| c.is_alphabetic() || c == '_') || path.ends_with('.') {
+        return None;
+    }
+
+    let mut rest = &inner[path_len..];
+    let mut default = None;
+    if let Some(after) = rest.strip_prefix('|') {
+        let after = after.trim_start();
+        let len = match after.chars().next() {
+            Some(q @ ('"' | '\'')) => after[1..].find(q).map(|i| i + 2).unwrap_or(after.len()),
+            _ => after.find(':').unwrap_or(after.len()),
+        };
+        default = Some(&after[..len]);
+        rest = &after[len..];
+    }
+
+    let format = match rest.trim().strip_prefix(':') {
+        Some(spec) => Some(spec.trim()),
+        None if rest.trim().is_empty() => None,
+        None => return None,
+    };
+
+    let error = |message: String| PackardError::Syntax { message, span: Some(span.clone()) };
+    let placeholder = (|| {
+        let default = match default {
+            Some(literal) => match parse_expr(literal) {
+                Ok(Expr::Literal(value)) => Some(value),
+                _ => return Err(error(format!("default for {{{}}} must be a literal value: {}", path, literal))),
+            },
+            None => None,
+        };
+        let format = match format {
+            Some(spec) => Some(Format::parse(spec).ok_or_else(|| error(format!("unknown format ':{}' for {{{}}}", spec, path)))?),
+            None => None,
+        };
+        Ok(Placeholder { path: path.to_string(), default, format, span: span.clone() })
+    })();
+
+    Some((placeholder, close + 1))
+}
+
+/// An `{if: ...}` block still waiting for its `{endif}`.
+struct Frame {
+    condition: Option<Box<Condition>>,
+    span: Span,
+    then: Vec<Node>,
+    otherwise: Option<Vec<Node>>,
+}
+
+impl Frame {
+    fn nodes(&mut self) -> &mut Vec<Node> {
+        match &mut self.otherwise {
+            Some(otherwise) => otherwise,
+            None => &mut self.then,
+        }
+    }
+
+    fn close(self) -> Node {
+        Node::If {
+            condition: self.condition,
+            span: self.span,
+            then: self.then,
+            otherwise: self.otherwise.unwrap_or_default(),
+        }
+    }
+}
+
+/// Builds the node tree while scanning.
+struct Builder {
+    root: Vec<Node>,
+    stack: Vec<Frame>,
+    text: String,
+}
+
+impl Builder {
+    fn nodes(&mut self) -> &mut Vec<Node> {
+        match self.stack.last_mut() {
+            Some(frame) => frame.nodes(),
+            None => &mut self.root,
+        }
+    }
+
+    fn flush(&mut self) {
+        if !self.text.is_empty() {
+            let text = std::mem::take(&mut self.text);
+            self.nodes().push(Node::Text(text));
+        }
+    }
+
+    /// Skip markup at `from..to`. If it is alone on its line the whole line
+    /// goes, so block directives and choice lines leave no blank lines behind.
+    fn skip(&mut self, text: &str, body_start: usize, from: usize, to: usize) -> usize {
+        let blank = |s: &str| s.chars().all(|c| c == ' ' || c == '\t' || c == '\r');
+        let line_start = text[..from].rfind('\n').map(|i| i + 1).unwrap_or(0).max(body_start);
+        let line_end = text[to..].find('\n').map(|i| to + i).unwrap_or(text.len());
+
+        if blank(&text[line_start..from]) && blank(&text[to..line_end]) {
+            let kept = self.text.trim_end_matches([' ', '\t']).len();
+            self.text.truncate(kept);
+            (line_end + 1).min(text.len())
+        } else {
+            to
+        }
+    }
+}
+
+impl Template {
+    /// Parse the prose in `source.text[start..end]`, leaving out the `skip`
+    /// spans (the scene's choices). Code, callouts and comments, as found by
+    /// [`parser::scan_verbatim`], are kept as written. Markup problems are
+    /// returned with the template.
+    pub fn parse(source: &Source, start: usize, end: usize, skip: &[Span]) -> (Template, Vec<PackardError>) {
+        let text = &source.text[..end];
+        let mut errors = Vec::new();
+        let mut builder = Builder { root: Vec::new(), stack: Vec::new(), text: String::new() };
+        let mut skip = skip.iter().peekable();
+        let verbatim = parser::scan_verbatim(source, start, end);
+        let mut verbatim = verbatim.iter().peekable();
+        let mut pos = start;
+
+        while pos < text.len() {
+            if let Some(span) = skip.next_if(|s| s.start <= pos) {
+                pos = builder.skip(text, start, span.start, span.end.max(pos));
+                continue;
+            }
+            if let Some(span) = verbatim.next_if(|s| s.start <= pos) {
+                if span.end > pos {
+                    builder.text.push_str(&text[pos..span.end]);
+                    pos = span.end;
+                }
+                continue;
+            }
+
+            let rest = &text[pos..];
+            let line_end = rest.find('\n').map(|i| pos + i).unwrap_or(text.len());
+
+            if rest.starts_with("{if:") {
+                if let Some(close) = find_closing(text, pos + 4, line_end, '{', '}') {
+                    let span = source.span(pos, close + 1);
+                    let condition = parse_condition(&text[pos + 4..close])
+                        .map_err(|e| errors.push(e.with_span(&span)))
+                        .ok()
+                        .map(Box::new);
+                    pos = builder.skip(text, start, pos, close + 1);
+                    builder.flush();
+                    builder.stack.push(Frame { condition, span, then: Vec::new(), otherwise: None });
+                    continue;
+                }
+            }
+
+            if rest.starts_with("{else}") {
+                let span = source.span(pos, pos + "{else}".len());
+                pos = builder.skip(text, start, pos, span.end);
+                builder.flush();
+                match builder.stack.last_mut() {
+                    Some(frame) if frame.otherwise.is_none() => frame.otherwise = Some(Vec::new()),
+                    _ => errors.push(PackardError::Syntax {
+                        message: "{else} without a matching {if: ...}".to_string(),
+                        span: Some(span),
+                    }),
+                }
+                continue;
+            }
+
+            if rest.starts_with("{endif}") {
+                let span = source.span(pos, pos + "{endif}".len());
+                pos = builder.skip(text, start, pos, span.end);
+                builder.flush();
+                match builder.stack.pop() {
+                    Some(frame) => {
+                        let node = frame.close();
+                        builder.nodes().push(node);
+                    }
+                    None => errors.push(PackardError::Syntax {
+                        message: "{endif} without a matching {if: ...}".to_string(),
+                        span: Some(span),
+                    }),
+                }
+                continue;
+            }
+
+            let variation = rest.strip_prefix('{').and_then(|r| r.chars().next()).and_then(Variation::from_sigil);
+            if let Some(kind) = variation {
+                if let Some((ranges, next)) = scan_variation(text, pos, line_end) {
+                    let options = ranges
+                        .into_iter()
+                        .map(|(from, to)| {
+                            let (option, option_errors) = Template::parse(source, from, to, &[]);
+                            errors.extend(option_errors);
+                            option.nodes
+                        })
+                        .collect();
+                    builder.flush();
+                    builder.nodes().push(Node::Variation { kind, span: source.span(pos, next), options });
+                    pos = next;
+                    continue;
+                }
+            }
+
+            if rest.starts_with('{') {
+                if let Some((placeholder, next)) = parse_placeholder(source, pos, line_end) {
+                    match placeholder {
+                        Ok(placeholder) => {
+                            builder.flush();
+                            builder.nodes().push(Node::Placeholder(placeholder));
+                        }
+                        Err(e) => {
+                            errors.push(e);
+                            builder.text.push_str(&rest[..next - pos]);
+                        }
+                    }
+                    pos = next;
+                    continue;
+                }
+            }
+
+            let ch = rest.chars().next().unwrap();
+            builder.text.push(ch);
+            pos += ch.len_utf8();
+        }
+
+        builder.flush();
+        while let Some(frame) = builder.stack.pop() {
+            errors.push(PackardError::Syntax {
+                message: "{if: ...} is missing its {endif}".to_string(),
+                span: Some(frame.span.clone()),
+            });
+            let node = frame.close();
+            builder.nodes().push(node);
+        }
+
+        (Template { nodes: builder.root }, errors)
+    }
+
+    /// Template with no markup, just `text`.
+    pub fn text(text: &str) -> Template {
+        Template { nodes: vec![Node::Text(text.to_string())] }
+    }
+
+    /// Template for a one-line piece of text such as a choice label.
+    pub fn parse_fragment(source: &Source, span: &Span) -> (Template, Vec<PackardError>) {
+        Template::parse(source, span.start, span.end, &[])
+    }
+
+    /// Render the text of scene `scene` for `state`, taking character
+    /// properties from `vault`. Variations pick their option from the scene's
+    /// visit count and the seed of the state's rng. A condition that fails to
+    /// evaluate is treated as false, and a placeholder with no value and no
+    /// default renders empty; either way the error is added to `errors`.
+    pub fn render(&self, state: &State, vault: &Vault, scene: &str, errors: &mut Vec<PackardError>) -> String {
+        let mut out = String::new();
+        let context = Context { state, vault, scene, values: None };
+        render_nodes(&self.nodes, &context, errors, &mut out);
+        out
+    }
+
+    /// Like [`Template::render`], with every placeholder left as a marker
+    /// character and its value in the returned list, so the text can be
+    /// taken apart without the values getting in the way. [`fill`] puts
+    /// the values back.
+    pub(crate) fn render_marked(
+        &self,
+        state: &State,
+        vault: &Vault,
+        scene: &str,
+        errors: &mut Vec<PackardError>,
+    ) -> (String, Vec<String>) {
+        let mut out = String::new();
+        let values = RefCell::new(Vec::new());
+        let context = Context { state, vault, scene, values: Some(&values) };
+        render_nodes(&self.nodes, &context, errors, &mut out);
+        (out, values.into_inner())
+    }
+
+    /// Every placeholder in the text.
+    pub fn placeholders(&self) -> Vec<&Placeholder> {
+        let mut found = Vec::new();
+        collect_placeholders(&self.nodes, &mut found);
+        found
+    }
+
+    /// Every condition in the prose, with the span of its `{if: ...}`.
+    pub fn conditions(&self) -> Vec<(&Condition, &Span)> {
+        let mut found = Vec::new();
+        collect_conditions(&self.nodes, &mut found);
+        found
+    }
+
+    /// Point `visited` and `visits` in the prose's conditions at the scene
+    /// ids in `ids`; see [`Expr::rename_scenes`].
+    pub(crate) fn rename_scenes(&mut self, ids: &HashMap<String, String>) {
+        rename_scenes(&mut self.nodes, ids);
+    }
+}
+
+/// What a render reads from besides the template itself.
+struct Context<'a> {
+    state: &'a State,
+    vault: &'a Vault,
+    scene: &'a str,
+    /// Where placeholder values go instead of the text, if anywhere
+    values: Option<&'a RefCell<Vec<String>>>,
+}
+
+/// Placeholder markers are characters from Supplementary Private Use Area-A.
+const MARKER_BASE: u32 = 0xF0000;
+const MARKER_COUNT: usize = 0xFFFE;
+
+/// Put the values back in text from [`Template::render_marked`].
+pub(crate) fn fill(text: &str, values: &[String]) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match (c as u32).checked_sub(MARKER_BASE).and_then(|i| values.get(i as usize)) {
+            Some(value) => out.push_str(value),
+            None => out.push(c),
+        }
+    }
+    out
+}
+
+fn render_nodes(nodes: &[Node], context: &Context, errors: &mut Vec<PackardError>, out: &mut String) {
+    let Context { state, vault, scene, values } = *context;
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Placeholder(placeholder) => {
+                let property = vault.is_property_path(&placeholder.path);
+                let value = if property {
+                    vault.property(&placeholder.path)
+                } else {
+                    state.get(&placeholder.path).cloned()
+                };
+                let value = match (value, &placeholder.default) {
+                    (Some(value), _) => value,
+                    (None, Some(default)) => default.clone(),
+                    (None, None) if property => {
+                        errors.push(PackardError::Runtime {
+                            message: format!("no property '{}'", placeholder.path),
+                            span: Some(placeholder.span.clone()),
+                        });
+                        Value::Null
+                    }
+                    (None, None) => state
+                        .read(&placeholder.path, Some(&Value::String(String::new())))
+                        .unwrap_or_else(|e| {
+                            errors.push(e.with_span(&placeholder.span));
+                            Value::Null
+                        }),
+                };
+                let text = match placeholder.format {
+                    Some(format) => format.apply(&value),
+                    None => display(&value),
+                };
+                match values.map(|v| v.borrow_mut()) {
+                    Some(mut values) if values.len() < MARKER_COUNT => {
+                        out.push(char::from_u32(MARKER_BASE + values.len() as u32).unwrap());
+                        values.push(text);
+                    }
+                    _ => out.push_str(&text),
+                }
+            }
+            Node::If { condition, span, then, otherwise } => {
+                let holds = match condition {
+                    Some(condition) => condition.evaluate_at(state, &format!("{}@{}", scene, span.start)).unwrap_or_else(|e| {
+                        errors.push(e.with_span(span));
+                        false
+                    }),
+                    None => false,
+                };
+                render_nodes(if holds { then } else { otherwise }, context, errors, out);
+            }
+            Node::Variation { kind, span, options } => {
+                let visit = state.visits(scene).saturating_sub(1) as usize;
+                let seed = state.rng.seed() ^ stable_hash(scene) ^ (span.start as u64).wrapping_mul(0x9e3779b97f4a7c15);
+                if let Some(i) = kind.pick(visit, options.len(), seed) {
+                    render_nodes(&options[i], context, errors, out);
+                }
+            }
+        }
+    }
+}
+
+impl Node {
+    /// The node lists nested inside this one, in every branch and option.
+    fn children(&self) -> Vec<&[Node]> {
+        match self {
+            Node::If { then, otherwise, .. } => vec![then, otherwise],
+            Node::Variation { options, .. } => options.iter().map(Vec::as_slice).collect(),
+            Node::Text(_) | Node::Placeholder(_) => Vec::new(),
+        }
+    }
+}
+
+fn collect_placeholders<'a>(nodes: &'a [Node], found: &mut Vec<&'a Placeholder>) {
+    for node in nodes {
+        if let Node::Placeholder(placeholder) = node {
+            found.push(placeholder);
+        }
+        for children in node.children() {
+            collect_placeholders(children, found);
+        }
+    }
+}
+
+fn collect_conditions<'a>(nodes: &'a [Node], found: &mut Vec<(&'a Condition, &'a Span)>) {
+    for node in nodes {
+        if let Node::If { condition: Some(condition), span, .. } = node {
+            found.push((condition, span));
+        }
+        for children in node.children() {
+            collect_conditions(children, found);
+        }
+    }
+}
+
+fn rename_scenes(nodes: &mut [Node], ids: &HashMap<String, String>) {
+    for node in nodes {
+        match node {
+            Node::If { condition, then, otherwise, .. } => {
+                if let Some(condition) = condition {
+                    condition.rename_scenes(ids);
+                }
+                rename_scenes(then, ids);
+                rename_scenes(otherwise, ids);
+            }
+            Node::Variation { options, .. } => options.iter_mut().for_each(|o| rename_scenes(o, ids)),
+            Node::Text(_) | Node::Placeholder(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::character::Character;
+    use crate::parser::scan_choices;
+    use crate::vault::tests::vault;
+
+    fn template(text: &str) -> (Template, Vec<PackardError>) {
+        let source = Source::new("test.md", text);
+        let skip: Vec<_> = scan_choices(&source, 0, text.len()).0.into_iter().map(|c| c.span).collect();
+        Template::parse(&source, 0, text.len(), &skip)
+    }
+
+    fn render(text: &str, state: &State) -> String {
+        let (template, errors) = template(text);
+        assert!(errors.is_empty(), "{:?}", errors);
+        let mut errors = Vec::new();
+        let out = template.render(state, &vault(&[]), "test", &mut errors);
+        assert!(errors.is_empty(), "{:?}", errors);
+        out
+    }
+
+    fn curious(value: i64) -> State {
+        let mut state = State::new();
+        state.set("player.curiosity", serde_yaml::Value::Number(value.into()));
+        state
+    }
+
+    #[test]
+    fn test_inline_conditionals() {
+        let text = "The room is quiet.{if: player.curiosity > 20} You notice scratches.{else} Nothing stands out.{endif}";
+        assert_eq!(render(text, &curious(30)), "The room is quiet. You notice scratches.");
+        assert_eq!(render(text, &curious(0)), "The room is quiet. Nothing stands out.");
+    }
+
+    #[test]
+    fn test_block_conditionals_leave_no_blank_lines() {
+        let text = "Intro\n{if: player.curiosity > 20}\nScratches.\n  {if: visited(\"journal\")}\n  They match the journal.\n  {endif}\n{else}\nNothing.\n{endif}\nOutro\n";
+        assert_eq!(render(text, &curious(30)), "Intro\nScratches.\nOutro\n");
+        assert_eq!(render(text, &curious(0)), "Intro\nNothing.\nOutro\n");
+
+        let mut state = curious(30);
+        state.record_visit("journal");
+        assert_eq!(render(text, &state), "Intro\nScratches.\n  They match the journal.\nOutro\n");
+    }
+
+    #[test]
+    fn test_choices_are_left_out() {
+        let text = "Where now?\n{if: player.curiosity > 20}[[secret|Secret]]\n[[start|Back]](x = 1) and more\n`{if: code}`\n";
+        assert_eq!(render(text, &curious(30)), "Where now?\n and more\n`{if: code}`\n");
+    }
+
+    #[test]
+    fn test_code_comments_and_callouts_are_verbatim() {
+        let text = "```\n{if: x > 1}\n{player.name}\n```\nA %% {endif} {&a|b} %% b <!--\n{else}\n--> c\n> [!note] {player.name}\n> {endif}\nEnd {player.curiosity}";
+        assert_eq!(
+            render(text, &curious(3)),
+            "```\n{if: x > 1}\n{player.name}\n```\nA %% {endif} {&a|b} %% b <!--\n{else}\n--> c\n> [!note] {player.name}\n> {endif}\nEnd 3"
+        );
+    }
+
+    #[test]
+    fn test_markup_errors() {
+        let (_, errors) = template("{if: x >}a{endif}\n{else}\n{endif}\n{if: y > 1}never closed");
+        let lines: Vec<_> = errors.iter().map(|e| e.span().unwrap().line).collect();
+        assert_eq!(lines, vec![1, 2, 3, 4]);
+
+        let (template, _) = template("{if: x > 1}a{endif}{if: name == 3}b{endif}");
+        assert_eq!(template.conditions().len(), 2);
+        let mut state = State::new();
+        state.set("name", serde_yaml::Value::String("Ada".to_string()));
+        let mut errors = Vec::new();
+        assert_eq!(template.render(&state, &vault(&[]), "test", &mut errors), "");
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn test_placeholders() {
+        let mut state = curious(7);
+        state.set("player.name", Value::String("ada lovelace".to_string()));
+        state.set("gold", Value::Number(3.5.into()));
+        state.set("player.inventory", Value::Sequence(vec![Value::String("key".into()), Value::String("map".into())]));
+
+        assert_eq!(render("Hello, {player.name:title}! ({player.curiosity})", &state), "Hello, Ada Lovelace! (7)");
+        assert_eq!(render("{player.curiosity:03}|{player.curiosity:3}|{gold:.2}|{player.name:upper}", &state), "007|  7|3.50|ADA LOVELACE");
+        assert_eq!(render("You carry {player.inventory}.", &state), "You carry key, map.");
+        assert_eq!(render("{player.title|\"stranger\"} and {player.lives|3:02}", &state), "stranger and 03");
+        assert_eq!(render("Missing: [{player.mood}]", &state), "Missing: []");
+        assert_eq!(render("{ not a placeholder } and {x + 1}", &state), "{ not a placeholder } and {x + 1}");
+
+        let (_, errors) = template("{gold:wide}\n{gold|x + 1}");
+        let lines: Vec<_> = errors.iter().map(|e| e.span().unwrap().line).collect();
+        assert_eq!(lines, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_variations() {
+        let text = "{&Quiet.|Still quiet.|Silent.} {@tick|tock} {!New!}|{~a|b|c}";
+        let (template, errors) = template(text);
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let mut state = State::new();
+        state.rng = Rng::new(42);
+        let mut rendered = Vec::new();
+        let mut shuffled = Vec::new();
+        for _ in 0..6 {
+            state.record_visit("hub");
+            let out = template.render(&state, &vault(&[]), "hub", &mut Vec::new());
+            let (fixed, pick) = out.split_once('|').unwrap();
+            rendered.push(fixed.to_string());
+            shuffled.push(pick.to_string());
+        }
+        assert_eq!(
+            rendered,
+            vec!["Quiet. tick New!", "Still quiet. tock ", "Silent. tick ", "Silent. tock ", "Silent. tick ", "Silent. tock "]
+        );
+
+        // Every option once per pass, and the same order for the same seed
+        for pass in shuffled.chunks(3) {
+            let mut pass = pass.to_vec();
+            pass.sort();
+            assert_eq!(pass, vec!["a", "b", "c"]);
+        }
+        let mut again = State::new();
+        again.rng = Rng::new(42);
+        again.record_visit("hub");
+        let first = template.render(&again, &vault(&[]), "hub", &mut Vec::new());
+        assert_eq!(first.split_once('|').unwrap().1, shuffled[0]);
+    }
+
+    #[test]
+    fn test_variation_options_hold_markup() {
+        let mut state = curious(30);
+        state.set("player.name", Value::String("Ada".to_string()));
+        assert_eq!(render("{&Hi {player.name}, it's {if: player.curiosity > 20}odd{else}fine{endif}|Bye}", &state), "Hi Ada, it's odd");
+        assert_eq!(render("{&unclosed|option", &state), "{&unclosed|option");
+    }
+
+    #[test]
+    fn test_character_properties() {
+        let mut vault = vault(&[]);
+        let keeper = Character::from_markdown("keeper".to_string(), "---\nname: The Old Keeper\nage: 80\n---\n").unwrap();
+        vault.characters.insert("keeper".to_string(), keeper);
+
+        let (known, _) = template("{characters.keeper.name} ({characters.keeper.age}) {characters.ghost.name|\"nobody\"}");
+        let mut errors = Vec::new();
+        assert_eq!(known.render(&State::new(), &vault, "test", &mut errors), "The Old Keeper (80) nobody");
+        assert!(errors.is_empty());
+
+        let (missing, _) = template("[{characters.keeper.mood}]");
+        assert_eq!(missing.render(&State::new(), &vault, "test", &mut errors), "[]");
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn test_strict_placeholders() {
+        let mut state = State::new();
+        state.undefined = crate::effects::UndefinedPolicy::Strict;
+        let (template, _) = template("Hi {player.name}");
+        let mut errors = Vec::new();
+        assert_eq!(template.render(&state, &vault(&[]), "test", &mut errors), "Hi ");
+        assert_eq!(errors[0].span().unwrap().column, 4);
+    }
+}
@@ -1,74 +1,375 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::Path;
 use walkdir::WalkDir;
-use crate::scene::Scene;
+use crate::scene::{heading_target, Scene};
+use crate::template::Template;
 use crate::character::Character;
+use crate::entity::Entity;
+use crate::check::Diagnostic;
+use crate::error::{PackardError, Result};
+use crate::manifest::Manifest;
+use crate::parser::{self, Source};
+
+/// Note types `Vault::load` knows by name. Notes of any other `type` are
+/// still kept, as entities of that type.
+pub const NOTE_TYPES: &[&str] = &["scene", "character", "item", "location", "note"];
 
 pub struct Vault {
     pub scenes: HashMap<String, Scene>,
     pub characters: HashMap<String, Character>,
+    /// Every other kind of note (items, locations, plain notes and any
+    /// custom `type`), by type and then id.
+    pub entities: BTreeMap<String, HashMap<String, Entity>>,
+    /// Settings from `packard.yaml`, or the defaults.
+    pub manifest: Manifest,
+    /// Problems with the vault as a whole found while loading, such as two
+    /// notes with the same name. Reported by `validate`.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// A markdown file found while loading, before it is parsed.
+struct Note {
+    /// Path relative to the vault root without `.md`, `/`-separated
+    path: String,
+    file: String,
+    content: String,
+}
+
+impl Note {
+    fn stem(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+
+    /// Report frontmatter that is not valid YAML, then blank it out so the
+    /// note loads as if it had none, with its lines and offsets unchanged.
+    fn check_frontmatter(&mut self, diagnostics: &mut Vec<Diagnostic>) {
+        let Some(frontmatter) = parser::split_frontmatter(&self.content).0 else { return };
+        let Err(err) = parser::parse_frontmatter(&Source::new(&self.file, &self.content), frontmatter) else { return };
+        diagnostics.push(Diagnostic::from(err));
+
+        let start = self.content.find('\n').map_or(0, |i| i + 1);
+        let end = start + frontmatter.len();
+        let blank: String = self.content[start..end]
+            .chars()
+            .map(|c| if c == '\n' { "\n".to_string() } else { " ".repeat(c.len_utf8()) })
+            .collect();
+        self.content.replace_range(start..end, &blank);
+    }
+
+    /// The frontmatter `type`, or else what the note's folder implies.
+    fn note_type(&self, manifest: &Manifest, relative: &Path) -> String {
+        let data = parser::split_frontmatter(&self.content)
+            .0
+            .and_then(|frontmatter| parser::parse_frontmatter(&Source::new(&self.file, &self.content), frontmatter).ok());
+        if let Some(kind) = data.as_ref().and_then(|d| d.get("type")).and_then(|t| t.as_str()) {
+            return kind.trim().to_lowercase();
+        }
+
+        if Manifest::in_folder(relative, &manifest.folders.characters) {
+            "character"
+        } else if Manifest::in_folder(relative, &manifest.folders.items) {
+            "item"
+        } else {
+            "scene"
+        }
+        .to_string()
+    }
+}
+
+/// Paths a link could mean, the way Obsidian resolves them regardless of
+/// case: a full path wins outright, otherwise every path ending in the link
+/// matches. Only paths that differ in nothing but case need the exact case.
+fn link_matches<'a>(paths: impl Iterator<Item = &'a str> + Clone, link: &str) -> Vec<&'a str> {
+    let link = link.trim_start_matches('/');
+    let link = link.strip_suffix(".md").unwrap_or(link);
+    if let Some(exact) = paths.clone().find(|p| *p == link) {
+        return vec![exact];
+    }
+    let link = link.to_lowercase();
+    let full: Vec<&str> = paths.clone().filter(|p| p.to_lowercase() == link).collect();
+    if !full.is_empty() {
+        return full;
+    }
+    let suffix = format!("/{}", link);
+    paths.filter(|p| p.to_lowercase().ends_with(&suffix)).collect()
 }
 
 impl Vault {
-    pub fn load(path: &str) -> Result<Self, String> {
-        let mut scenes = HashMap::new();
+    pub fn load(path: &str) -> Result<Self> {
+        let mut scene_notes = Vec::new();
         let mut characters = HashMap::new();
+        let mut entities: BTreeMap<String, HashMap<String, Entity>> = BTreeMap::new();
+        let mut diagnostics = Vec::new();
         let vault_path = Path::new(path);
 
         if !vault_path.exists() {
-            return Err(format!("Vault path does not exist: {}", path));
+            return Err(PackardError::InvalidVault {
+                path: vault_path.to_path_buf(),
+                message: "vault path does not exist".to_string(),
+            });
         }
+        let mut manifest = Manifest::load(vault_path)?;
 
-        // Walk through all markdown files in the vault
+        // Walk through all markdown files in the vault, in a fixed order so
+        // that which duplicate wins does not depend on the filesystem
         for entry in WalkDir::new(vault_path)
+            .sort_by_file_name()
             .into_iter()
             .filter_map(|e| e.ok())
             .filter(|e| e.path().extension().and_then(|s| s.to_str()) == Some("md"))
         {
             let file_path = entry.path();
-            
-            // Skip .obsidian folder
-            if file_path.components().any(|c| c.as_os_str() == ".obsidian") {
+            let relative = file_path.strip_prefix(vault_path).unwrap_or(file_path);
+            if manifest.is_ignored(relative) {
                 continue;
             }
 
             // Read file
             let content = fs::read_to_string(file_path)
-                .map_err(|e| format!("Failed to read {}: {}", file_path.display(), e))?;
-
-            // Get ID from filename (without .md)
-            let id = file_path
-                .file_stem()
-                .and_then(|s| s.to_str())
-                .ok_or("Invalid filename")?
-                .to_string();
+                .map_err(|source| PackardError::Io { path: file_path.to_path_buf(), source })?;
 
-            // Check if file is in characters folder
-            let is_character = file_path
+            let parts = relative
+                .with_extension("")
                 .components()
-                .any(|c| c.as_os_str() == "characters");
-
-            if is_character {
-                let character = Character::from_markdown(id.clone(), &content)?;
-                characters.insert(id, character);
-            } else {
-                let scene = Scene::from_markdown(id.clone(), &content)?;
-                scenes.insert(id, scene);
+                .map(|c| c.as_os_str().to_str().map(str::to_string))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| PackardError::InvalidVault {
+                    path: file_path.to_path_buf(),
+                    message: "invalid filename".to_string(),
+                })?;
+            let mut note = Note { path: parts.join("/"), file: file_path.display().to_string(), content };
+            note.check_frontmatter(&mut diagnostics);
+
+            // Characters and other entities are looked up by name alone
+            let notes = match note.note_type(&manifest, relative).as_str() {
+                "scene" => None,
+                "character" => Some(&mut characters),
+                kind => Some(entities.entry(kind.to_string()).or_default()),
+            };
+            match notes {
+                Some(notes) if notes.contains_key(note.stem()) => diagnostics.push(
+                    Diagnostic::warning(
+                        "duplicate-name",
+                        format!("'{}' is already defined by another note; this one is ignored", note.stem()),
+                    )
+                    .with_file(&note.file),
+                ),
+                Some(notes) => {
+                    let entity = Entity::from_source(note.stem().to_string(), &note.content, &note.file)?;
+                    notes.insert(note.stem().to_string(), entity);
+                }
+                None => scene_notes.push(note),
+            }
+        }
+
+        if scene_notes.is_empty() {
+            return Err(PackardError::InvalidVault {
+                path: vault_path.to_path_buf(),
+                message: "no markdown files found in vault".to_string(),
+            });
+        }
+
+        // Each scene is known by the shortest trailing part of its path that
+        // no other scene shares
+        let paths = || scene_notes.iter().map(|n| n.path.as_str());
+        let mut scenes = HashMap::new();
+        for note in &scene_notes {
+            let parts: Vec<&str> = note.path.split('/').collect();
+            let id = (1..=parts.len())
+                .map(|n| parts[parts.len() - n..].join("/"))
+                .find(|id| link_matches(paths(), id) == [note.path.as_str()])
+                .unwrap_or_else(|| note.path.clone());
+            let mut scene = Scene::from_source(id.clone(), &note.content, &note.file)?;
+            scene.path = note.path.clone();
+            for mut sub in std::mem::take(&mut scene.subscenes) {
+                sub.path = format!("{}{}", note.path, &sub.id[id.len()..]);
+                scenes.insert(sub.id.clone(), sub);
+            }
+            scenes.insert(id, scene);
+        }
+
+        // Links ignore case, so `Intro` and `intro` collide too
+        let mut by_stem: BTreeMap<String, Vec<&Note>> = BTreeMap::new();
+        for note in &scene_notes {
+            by_stem.entry(note.stem().to_lowercase()).or_default().push(note);
+        }
+        for notes in by_stem.into_values() {
+            let stem = notes[0].stem();
+            if notes.len() > 1 {
+                let paths: Vec<&str> = notes.iter().map(|n| n.path.as_str()).collect();
+                for note in &notes {
+                    diagnostics.push(
+                        Diagnostic::warning(
+                            "duplicate-name",
+                            format!(
+                                "{} notes are named '{}' ({}); link to them by path, e.g. [[{}]]",
+                                notes.len(),
+                                stem,
+                                paths.join(", "),
+                                note.path
+                            ),
+                        )
+                        .with_file(&note.file),
+                    );
+                }
+            }
+            if characters.contains_key(stem) || entities.values().any(|e| e.contains_key(stem)) {
+                for note in &notes {
+                    diagnostics.push(
+                        Diagnostic::warning("duplicate-name", format!("scene '{}' has the same name as another note", stem))
+                            .with_file(&note.file),
+                    );
+                }
+            }
+        }
+
+        let mut vault = Vault {
+            scenes,
+            characters,
+            entities,
+            manifest: Manifest::default(),
+            diagnostics,
+        };
+
+        vault.link_choices();
+        vault.link_scene_names();
+        if let Some(id) = vault.resolve(&manifest.start) {
+            manifest.start = id.to_string();
+        }
+        vault.manifest = manifest;
+
+        Ok(vault)
+    }
+
+    /// Point links at the ids they resolve to, or at a sub-scene for
+    /// `[[note#Heading]]`, and label bare links with the title of what they
+    /// link to.
+    fn link_choices(&mut self) {
+        let links: Vec<(String, usize, String, Option<String>, String)> = self
+            .scenes
+            .values()
+            .flat_map(|scene| {
+                let vault = &*self;
+                scene.choices.iter().enumerate().map(move |(i, choice)| {
+                    let mut target = vault.resolve(&choice.target).and_then(|id| vault.get_scene(id));
+                    let mut heading = choice.heading.as_deref();
+                    // `[[note#Heading]]` leads to the sub-scene when there is
+                    // one, or into the sub-scene the heading is a section of
+                    if let (Some(note), Some(h)) = (target, heading) {
+                        let parts: Vec<&Scene> =
+                            std::iter::once(note).chain(note.parts.iter().filter_map(|id| vault.get_scene(id))).collect();
+                        if let Some((part, section)) = heading_target(&parts, h) {
+                            target = Some(part);
+                            heading = heading.filter(|_| section);
+                        }
+                    }
+                    let id = target.map_or(&choice.target, |t| &t.id);
+                    let label = match (choice.label.is_empty(), target, heading) {
+                        (false, _, _) => choice.label.clone(),
+                        (true, Some(target), Some(heading)) => format!("{} > {}", target.title, heading),
+                        (true, Some(target), None) => target.title.clone(),
+                        (true, None, _) => choice.target.clone(),
+                    };
+                    (scene.id.clone(), i, id.clone(), heading.map(str::to_string), label)
+                })
+            })
+            .collect();
+        for (scene, i, id, heading, label) in links {
+            let choice = &mut self.scenes.get_mut(&scene).unwrap().choices[i];
+            choice.target = id;
+            choice.heading = heading;
+            if choice.label.is_empty() {
+                choice.label_template = Template::text(&label);
+                choice.label = label;
             }
         }
+    }
+
+    /// Point `visited("Name")` and `visits("Name")` at the scene ids the
+    /// names resolve to, so they count the visits the runtime records.
+    fn link_scene_names(&mut self) {
+        let ids: HashMap<String, String> = self
+            .scenes
+            .values()
+            .flat_map(|scene| scene.scene_names())
+            .filter_map(|name| Some((name.to_string(), self.resolve_visit(name)?)))
+            .collect();
+        for scene in self.scenes.values_mut() {
+            scene.rename_scenes(&ids);
+        }
+    }
+
+    /// The key `visited(name)` counts under: the id of the scene `name`
+    /// links to, or for `note#^choice-id` that of a choice in it.
+    pub fn resolve_visit(&self, name: &str) -> Option<String> {
+        if let Some(id) = self.resolve(name) {
+            return Some(id.to_string());
+        }
+        if let Some((scene, choice)) = name.rsplit_once("#^") {
+            let scene = self.get_scene(&self.resolve_visit(scene)?)?;
+            return scene.choices.iter().find(|c| c.id == choice).map(|c| c.visit_key(&scene.id));
+        }
+        let (note, heading) = name.split_once('#')?;
+        let sub = format!("{}#{}", self.resolve(note)?, heading).to_lowercase();
+        self.scenes.values().find(|s| s.id.to_lowercase() == sub).map(|s| s.id.clone())
+    }
+
+    /// Ids of every scene `link` could mean: by path, or failing that by
+    /// one of its `aliases`. More than one means the link is ambiguous and
+    /// needs more of the path.
+    pub fn link_candidates(&self, link: &str) -> Vec<&str> {
+        let paths = self.scenes.values().map(|s| s.path.as_str());
+        let mut ids: Vec<&str> = link_matches(paths, link)
+            .into_iter()
+            .filter_map(|path| self.scenes.values().find(|s| s.path == path))
+            .map(|s| s.id.as_str())
+            .collect();
+        if ids.is_empty() {
+            let link = link.to_lowercase();
+            ids = self
+                .scenes
+                .values()
+                .filter(|s| s.aliases.iter().any(|a| a.to_lowercase() == link))
+                .map(|s| s.id.as_str())
+                .collect();
+        }
+        ids.sort();
+        ids
+    }
 
-        if scenes.is_empty() {
-            return Err("No markdown files found in vault".to_string());
+    /// Id of the one scene `link` means, by id or path, if there is exactly one.
+    pub fn resolve(&self, link: &str) -> Option<&str> {
+        if let Some(scene) = self.scenes.get(link) {
+            return Some(&scene.id);
+        }
+        match self.link_candidates(link).as_slice() {
+            [id] => Some(id),
+            _ => None,
         }
+    }
 
-        Ok(Vault { scenes, characters })
+    /// Lint the vault: dangling links, unreachable scenes, dead ends,
+    /// markup syntax errors, variables that are read but never written and
+    /// `visited` calls naming no scene.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        crate::check::check_vault(self)
     }
 
     pub fn get_scene(&self, id: &str) -> Option<&Scene> {
         self.scenes.get(id)
     }
 
+    /// The scene for the whole note `scene` is part of: the note's own
+    /// scene for a sub-scene `note#Heading`, otherwise `scene` itself.
+    pub fn note_of<'a>(&'a self, scene: &'a Scene) -> &'a Scene {
+        scene
+            .id
+            .split_once('#')
+            .and_then(|(note, _)| self.get_scene(note))
+            .unwrap_or(scene)
+    }
+
     pub fn list_scenes(&self) -> Vec<String> {
         let mut ids: Vec<_> = self.scenes.keys().cloned().collect();
         ids.sort();
@@ -79,9 +380,318 @@ impl Vault {
         self.characters.get(id)
     }
 
+    /// A non-scene, non-character note by type, e.g. `("item", "lamp")`.
+    pub fn get_entity(&self, kind: &str, id: &str) -> Option<&Entity> {
+        self.entities.get(kind)?.get(id)
+    }
+
+    /// The notes a property path prefix refers to: `characters`, or a type
+    /// of entity named exactly, e.g. `item`.
+    fn notes_for(&self, prefix: &str) -> Option<&HashMap<String, Entity>> {
+        match prefix {
+            "characters" => Some(&self.characters),
+            _ => self.entities.get(prefix),
+        }
+    }
+
+    /// Scene the story starts in, from the manifest.
+    pub fn start_scene(&self) -> &str {
+        &self.manifest.start
+    }
+
+    /// Whether `path` names a note property rather than a state variable:
+    /// it starts with `characters.`, a known entity type (`item.`,
+    /// `location.`, `note.`) or the type of an entity in the vault.
+    pub fn is_property_path(&self, path: &str) -> bool {
+        let Some((prefix, _)) = path.split_once('.') else {
+            return false;
+        };
+        let known = NOTE_TYPES.contains(&prefix) && !matches!(prefix, "scene" | "character");
+        known || self.notes_for(prefix).is_some()
+    }
+
+    /// Look up a note property by path, e.g. `characters.old_keeper.name` or
+    /// `item.lamp.weight`. `name`, `description` and `id` come from the note
+    /// itself; anything else from its frontmatter.
+    pub fn property(&self, path: &str) -> Option<serde_yaml::Value> {
+        let (kind, rest) = path.split_once('.')?;
+        let (id, key) = rest.split_once('.')?;
+        let entity = self.notes_for(kind)?.get(id)?;
+        match key {
+            "id" => Some(serde_yaml::Value::String(entity.id.clone())),
+            "name" => Some(serde_yaml::Value::String(entity.name.clone())),
+            "description" => Some(serde_yaml::Value::String(entity.description.clone())),
+            _ => entity.get_property(key).cloned(),
+        }
+    }
+
     pub fn list_characters(&self) -> Vec<String> {
         let mut ids: Vec<_> = self.characters.keys().cloned().collect();
         ids.sort();
         ids
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Build a vault from `(id, markdown)` pairs without touching the disk.
+    pub(crate) fn vault(notes: &[(&str, &str)]) -> Vault {
+        let scenes = notes
+            .iter()
+            .flat_map(|(id, content)| {
+                let mut scene = Scene::from_markdown(id.to_string(), content).unwrap();
+                let subscenes = std::mem::take(&mut scene.subscenes);
+                std::iter::once(scene).chain(subscenes)
+            })
+            .map(|scene| (scene.id.clone(), scene))
+            .collect();
+        let mut vault = Vault {
+            scenes,
+            characters: HashMap::new(),
+            entities: BTreeMap::new(),
+            manifest: Manifest::default(),
+            diagnostics: Vec::new(),
+        };
+        vault.link_choices();
+        vault.link_scene_names();
+        vault
+    }
+
+    #[test]
+    fn test_load_test_vault() {
+        let vault = Vault::load(concat!(env!("CARGO_MANIFEST_DIR"), "/../testVault")).unwrap();
+        assert!(vault.get_scene("start").is_some());
+        assert!(vault.get_character("old_keeper").is_some());
+        assert!(vault.get_scene("old_keeper").is_none());
+        assert_eq!(vault.manifest.title.as_deref(), Some("The Unfamiliar Room"));
+        assert_eq!(vault.start_scene(), "start");
+    }
+
+    /// Write `(path, content)` files to a scratch directory and load it.
+    fn load(name: &str, files: &[(&str, &str)]) -> Vault {
+        let root = std::env::temp_dir().join(format!("packard-{}-{}", name, std::process::id()));
+        for (file, content) in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        let vault = Vault::load(root.to_str().unwrap());
+        fs::remove_dir_all(&root).unwrap();
+        vault.unwrap()
+    }
+
+    #[test]
+    fn test_manifest_folders() {
+        let vault = load("manifest", &[
+            ("packard.yaml", "start: intro
+folders:
+  characters: cast
+  ignore: [templates]
+"),
+            ("intro.md", "---
+ending: true
+---
+Hello {item.lamp.name}."),
+            ("cast/ada.md", "---
+name: Ada
+---
+"),
+            ("characters/old.md", "---
+ending: true
+---
+Still a scene here."),
+            ("act1/cast/bob.md", "---
+name: Bob
+---
+"),
+            ("items/lamp.md", "---
+name: Brass lamp
+---
+"),
+            ("templates/scene.md", "[[nowhere|Broken template]]"),
+        ]);
+
+        assert_eq!(vault.start_scene(), "intro");
+        assert_eq!(vault.list_scenes(), vec!["intro", "old"]);
+        assert_eq!(vault.list_characters(), vec!["ada", "bob"]);
+        assert_eq!(vault.property("item.lamp.name").unwrap().as_str(), Some("Brass lamp"));
+    }
+
+    #[test]
+    fn test_notes_are_classified_by_type() {
+        let vault = load("types", &[
+            ("start.md", "{location.cellar.name} and {spell.light.cost}\n[[lobby|In]]"),
+            ("people/ada.md", "---\ntype: Character\nname: Ada\n---\n"),
+            ("characters/lobby.md", "---\ntype: scene\nending: true\n---\n"),
+            ("places/cellar.md", "---\ntype: location\nname: The Cellar\n---\n"),
+            ("lore/light.md", "---\ntype: spell\ncost: 3\n---\nMakes light."),
+            ("characters/bob.md", "---\nname: Bob\n---\n"),
+        ]);
+
+        assert_eq!(vault.list_scenes(), vec!["lobby", "start"]);
+        assert_eq!(vault.list_characters(), vec!["ada", "bob"]);
+        assert_eq!(vault.get_entity("location", "cellar").unwrap().name, "The Cellar");
+        assert_eq!(vault.get_entity("spell", "light").unwrap().description, "Makes light.");
+        assert_eq!(vault.property("location.cellar.name").unwrap().as_str(), Some("The Cellar"));
+        assert_eq!(vault.property("spell.light.cost").unwrap().as_i64(), Some(3));
+        assert!(vault.is_property_path("item.lamp.name") && !vault.is_property_path("player.name"));
+        assert!(!vault.is_property_path("items.count") && !vault.is_property_path("spells.known"));
+        assert!(vault.validate().is_empty());
+    }
+
+    #[test]
+    fn test_item_properties() {
+        let vault = load("items", &[
+            ("start.md", "---\nending: true\n---\n"),
+            ("items/lamp.md", "---\nname: Brass lamp\nweight: 2\nlit: false\ntags: [light, metal]\n---\nDented."),
+        ]);
+
+        let lamp = vault.get_entity("item", "lamp").unwrap();
+        assert_eq!((lamp.id.as_str(), lamp.name.as_str(), lamp.description.as_str()), ("lamp", "Brass lamp", "Dented."));
+        assert_eq!(lamp.get_property("weight").unwrap().as_i64(), Some(2));
+        assert_eq!(lamp.get_property("lit").unwrap().as_bool(), Some(false));
+        assert_eq!(lamp.get_property("tags").unwrap().as_sequence().unwrap().len(), 2);
+        assert!(lamp.get_property("name").is_none());
+        assert_eq!(vault.property("item.lamp.weight").unwrap().as_i64(), Some(2));
+    }
+
+    #[test]
+    fn test_custom_type_properties() {
+        let vault = load("custom", &[
+            ("start.md", "---\nending: true\n---\n"),
+            ("lore/idol.md", "---\ntype: Relic\nweight: 2.5\ncursed: true\n---\nA small idol."),
+        ]);
+
+        assert_eq!(vault.list_scenes(), vec!["start"]);
+        let idol = vault.get_entity("relic", "idol").unwrap();
+        assert_eq!(idol.name, "idol");
+        assert_eq!(idol.description, "A small idol.");
+        assert_eq!(idol.get_property("weight").unwrap().as_f64(), Some(2.5));
+        assert_eq!(idol.get_property("type").unwrap().as_str(), Some("Relic"));
+        assert_eq!(vault.property("relic.idol.cursed").unwrap().as_bool(), Some(true));
+        assert_eq!(vault.property("relic.idol.name").unwrap().as_str(), Some("idol"));
+    }
+
+    #[test]
+    fn test_obsidian_link_forms() {
+        let vault = load("links", &[
+            ("start.md", "[[journal]]\n[[Diary]]\n[[hall#The Stairs]]\n[[hall#Attic|Climb]]\n[[nowhere]]\n[[hall#Landing]]"),
+            ("journal.md", "---\ntitle: The Journal\naliases: [Diary, Log]\nending: true\n---\n"),
+            ("hall.md", "---\nalias: Corridor\nending: true\n---\nLong.\n## The Stairs\nUp.\n### Landing\nDusty.\n"),
+        ]);
+
+        let choices = &vault.get_scene("start").unwrap().choices;
+        let links: Vec<_> = choices
+            .iter()
+            .map(|c| (c.target.as_str(), c.heading.as_deref(), c.label.as_str()))
+            .collect();
+        assert_eq!(
+            links,
+            vec![
+                ("journal", None, "The Journal"),
+                ("journal", None, "The Journal"),
+                ("hall#The Stairs", None, "The Stairs"),
+                ("hall", Some("Attic"), "Climb"),
+                ("nowhere", None, "nowhere"),
+                ("hall#The Stairs", Some("Landing"), "The Stairs > Landing"),
+            ]
+        );
+        assert_eq!(vault.resolve("corridor"), Some("hall"));
+
+        let mut codes: Vec<_> = vault.validate().iter().map(|d| d.code).collect();
+        codes.sort();
+        assert_eq!(codes, vec!["dangling-link", "unknown-heading"]);
+    }
+
+    #[test]
+    fn test_broken_frontmatter_is_reported() {
+        let vault = load("broken", &[
+            ("start.md", "---\ntype: [scene\n---\n[[characters/keeper|Talk]]\n[[start|Wait]] ^wait"),
+            ("characters/keeper.md", "---\nname: \"Keeper\n---\n"),
+        ]);
+
+        assert!(vault.get_character("keeper").is_some());
+        let choices = &vault.get_scene("start").unwrap().choices;
+        assert_eq!(choices[1].span.line, 5);
+
+        let yaml: Vec<_> = vault.validate().into_iter().filter(|d| d.code == "yaml").collect();
+        let files: Vec<_> = yaml.iter().map(|d| d.file.as_deref().unwrap().ends_with("keeper.md")).collect();
+        assert_eq!(files, vec![true, false]);
+        assert_eq!(yaml[1].span.as_ref().unwrap().line, 3);
+    }
+
+    #[test]
+    fn test_links_ignore_case() {
+        let vault = load("case", &[
+            ("start.md", "[[Chapter/Intro|Read]]\n[[HALL|Hall]]\n[[act/Room|Room]]\n[[act/ROOM|Which?]]"),
+            ("chapter/intro.md", "---\nending: true\n---\n"),
+            ("rooms/hall.md", "---\nending: true\n---\n"),
+            ("act/room.md", "[[start|Back]]"),
+            ("act/Room.md", "[[start|Back]]"),
+        ]);
+
+        let targets: Vec<_> = vault.get_scene("start").unwrap().choices.iter().map(|c| c.target.as_str()).collect();
+        assert_eq!(targets, vec!["intro", "hall", "act/Room", "act/ROOM"]);
+        assert_eq!(vault.link_candidates("act/ROOM"), vec!["act/Room", "act/room"]);
+
+        let mut codes: Vec<_> = vault.validate().iter().map(|d| d.code).collect();
+        codes.sort();
+        assert_eq!(codes, vec!["ambiguous-link", "duplicate-name", "duplicate-name", "unreachable"]);
+    }
+
+    #[test]
+    fn test_visit_names_resolve_like_links() {
+        let vault = load("visits", &[
+            (
+                "start.md",
+                "{if: visited(\"Diary\")}Again.{endif}\n[[journal|Read]](seen = visits(\"rooms/hall\")) ^read\n\
+                 {if: visited(\"hall#The Stairs\") OR visited(\"start#^read\") OR visited(\"nowhere\")}[[journal|Back]]",
+            ),
+            ("journal.md", "---\naliases: [Diary]\nending: true\n---\n"),
+            ("act1/rooms/hall.md", "---\nending: true\n---\n## The Stairs\nUp.\n"),
+        ]);
+
+        let start = vault.get_scene("start").unwrap();
+        let names = |scene: &Scene| scene.scene_names().into_iter().map(str::to_string).collect::<Vec<_>>();
+        assert_eq!(names(start), vec!["journal", "hall#The Stairs", "start#^read", "nowhere", "hall"]);
+
+        let unknown: Vec<_> = vault.validate().into_iter().filter(|d| d.code == "unknown-scene").collect();
+        assert_eq!(unknown.len(), 1);
+        assert!(unknown[0].message.contains("'nowhere'"));
+        assert_eq!(unknown[0].span.as_ref().unwrap().line, 3);
+    }
+
+    #[test]
+    fn test_path_qualified_ids() {
+        let vault = load("paths", &[
+            ("packard.yaml", "start: act1/intro\n"),
+            ("act1/intro.md", "[[act2/intro|On]]\n[[hall|Hall]]\n[[intro|Which?]]"),
+            ("act2/intro.md", "[[/act1/intro.md|Back]]"),
+            ("act2/rooms/hall.md", "---\nending: true\n---\n"),
+            ("characters/hall.md", "---\nname: Hall\n---\n"),
+            ("characters/more/hall.md", "---\nname: Other Hall\n---\n"),
+        ]);
+
+        assert_eq!(vault.list_scenes(), vec!["act1/intro", "act2/intro", "hall"]);
+        assert_eq!(vault.start_scene(), "act1/intro");
+        let intro = vault.get_scene("act1/intro").unwrap();
+        assert_eq!((intro.title.as_str(), intro.path.as_str()), ("intro", "act1/intro"));
+        assert_eq!(vault.get_scene("hall").unwrap().path, "act2/rooms/hall");
+
+        let targets: Vec<_> = intro.choices.iter().map(|c| c.target.as_str()).collect();
+        assert_eq!(targets, vec!["act2/intro", "hall", "intro"]);
+        assert_eq!(vault.get_scene("act2/intro").unwrap().choices[0].target, "act1/intro");
+        assert_eq!(vault.resolve("rooms/hall"), Some("hall"));
+        assert_eq!(vault.link_candidates("intro"), vec!["act1/intro", "act2/intro"]);
+        assert_eq!(vault.get_character("hall").unwrap().name, "Hall");
+
+        let mut codes: Vec<_> = vault.validate().iter().map(|d| d.code).collect();
+        codes.sort();
+        assert_eq!(
+            codes,
+            vec!["ambiguous-link", "duplicate-name", "duplicate-name", "duplicate-name", "duplicate-name"]
+        );
+    }
+}